@@ -0,0 +1,596 @@
+//! Request and response bodies of the HTTP API.
+//!
+//! Everything a client sends or receives as JSON is defined here, so the wire
+//! format is explicit and changes to it show up in one place.
+
+use crate::bmc::{BmcUser, ResetMode, WatchdogAction};
+use crate::chassis::RestorePolicy;
+use crate::config::{Action, Config, TokenConfig};
+use crate::fru::FruDevice;
+use crate::ipmi::{BootDevice, BootOptions, IdentifyState, PowerError, PowerErrorKind};
+use crate::sel::SelEntry;
+use crate::sensor::Sensor;
+use crate::state::PersistedState;
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+/// Body of `POST /power`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct PowerControlMsg {
+    #[serde(default)]
+    pub action: Option<String>,
+    /// Actions run in order after `action`, stopping at the first failure.
+    #[serde(default)]
+    pub actions: Vec<String>,
+    /// Why the server is being turned off, logged with the caller. Required
+    /// for destructive actions when `require_reason` is set.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Fields from newer clients, ignored unless `strict_requests` is set.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, serde_json::Value>,
+}
+
+/// Body of a successful `POST /power`, one result per action run.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct PowerControlResponse {
+    pub results: Vec<ControlResult>,
+}
+
+/// Body of a failed `POST /power`: the results of the actions run before the
+/// failing one, and its error.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct PowerControlFailure {
+    pub results: Vec<ControlResult>,
+    pub error: PowerError,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlResult {
+    On,
+    Off,
+    /// A graceful shutdown was handed to the OS, the server may still be on.
+    SoftOff,
+    /// The diagnostic interrupt was sent.
+    Diag,
+    /// The BMC reported a change still underway.
+    Pending,
+}
+
+/// Body of a successful `GET /power`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct PowerStatusResponse {
+    /// Last known reading, missing when the status is `unknown`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_on: Option<bool>,
+    pub status: PowerState,
+    /// The reading differs from the configured `expected_state`.
+    pub drifted: bool,
+    /// While powering on/off: seconds until this server usually gets there,
+    /// learned from previous transitions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_seconds: Option<u64>,
+    /// Why the status query failed, even when the state could be inferred.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<PowerError>,
+}
+
+/// Body of responses failing because of ipmitool.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct ErrorResponse {
+    pub error: PowerError,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerState {
+    On,
+    Off,
+    /// A change was requested or reported by the BMC but not yet confirmed,
+    /// `is_on` still shows the old state.
+    PoweringOn,
+    PoweringOff,
+    /// The BMC could not be queried.
+    Unknown,
+}
+
+/// Body of `POST /identify`. Without fields the LED blinks for the BMC's
+/// default interval, `interval: 0` turns it off.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct IdentifyMsg {
+    /// Seconds to blink the LED, at most 255.
+    #[serde(default)]
+    pub interval: Option<u8>,
+    /// Keep the LED on until it is turned off.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Body of `GET /identify` and of a successful `POST /identify`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Identify {
+    pub state: IdentifyState,
+}
+
+/// Body of `PUT /chassis/policy` and of its successful response.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct RestorePolicyMsg {
+    pub policy: RestorePolicy,
+}
+
+/// Body of `POST /bootdev`. Options left out are taken from `bootdev` in the
+/// config.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct BootDevMsg {
+    pub device: BootDevice,
+    #[serde(default)]
+    pub persistent: Option<bool>,
+    #[serde(default)]
+    pub efiboot: Option<bool>,
+}
+
+/// Body of a successful `POST /bootdev`, with the options applied.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct BootDev {
+    pub device: BootDevice,
+    #[serde(flatten)]
+    pub options: BootOptions,
+}
+
+/// Body of `POST /bmc/reset` and of its successful response.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct BmcResetMsg {
+    pub mode: ResetMode,
+    /// Why the BMC is being reset, logged with the caller. Required when
+    /// `require_reason` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Body of `GET /bmc/users`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct BmcUsers {
+    pub users: Vec<BmcUser>,
+}
+
+/// Body of `PUT /bmc/users/{id}/password`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BmcPasswordMsg {
+    pub password: String,
+}
+
+/// Body of `PUT /bmc/users/{id}/enabled` and of its successful response.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct BmcUserEnabled {
+    pub enabled: bool,
+}
+
+/// Body of `PUT /bmc/pef/filters/{id}/enabled` and of its successful
+/// response.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct PefFilterEnabled {
+    pub enabled: bool,
+}
+
+/// Body of `POST /watchdog`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct WatchdogMsg {
+    pub action: WatchdogAction,
+}
+
+/// Body of `POST /raw`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct RawMsg {
+    pub netfn: u8,
+    pub cmd: u8,
+    #[serde(default)]
+    pub data: Vec<u8>,
+}
+
+/// Body of a successful `POST /raw`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct RawResponse {
+    pub data: Vec<u8>,
+}
+
+/// Body of a successful `POST /oem/:command`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct OemOutput {
+    /// ipmitool's output, see `sanitize_output`. The format differs by
+    /// vendor.
+    pub output: String,
+    /// `output` was cut to `output_limit_bytes`.
+    pub truncated: bool,
+}
+
+/// Body of `GET /sensors`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Sensors {
+    pub sensors: Vec<Sensor>,
+}
+
+/// Body of `GET /thermal`, temperatures in degrees Celsius. The summary is
+/// missing when no temperature sensor has a reading.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Thermal {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub sensors: Vec<Temperature>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Temperature {
+    pub name: String,
+    pub celsius: f64,
+    pub state: String,
+}
+
+impl Thermal {
+    pub fn from_sensors(sensors: &[Sensor]) -> Self {
+        let sensors: Vec<Temperature> = sensors
+            .iter()
+            .filter_map(|s| {
+                Some(Temperature {
+                    name: s.name.clone(),
+                    celsius: s.celsius()?,
+                    state: s.state.clone(),
+                })
+            })
+            .collect();
+        let readings = sensors.iter().map(|t| t.celsius);
+        let count = sensors.len() as f64;
+        Thermal {
+            min: readings.clone().reduce(f64::min),
+            max: readings.clone().reduce(f64::max),
+            avg: (!sensors.is_empty()).then(|| readings.sum::<f64>() / count),
+            sensors,
+        }
+    }
+}
+
+/// Body of `GET /fans`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Fans {
+    pub fans: Vec<Fan>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Fan {
+    pub name: String,
+    /// Missing for fans without a reading, e.g. empty fan slots.
+    pub rpm: Option<f64>,
+    /// `ok`, or how far below the thresholds the fan is, see `Sensor`.
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_non_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_non_recoverable: Option<f64>,
+}
+
+impl Fans {
+    pub fn from_sensors(sensors: &[Sensor]) -> Self {
+        let fans = sensors
+            .iter()
+            .filter(|s| s.unit.as_deref() == Some("RPM"))
+            .map(|s| Fan {
+                name: s.name.clone(),
+                rpm: s.value,
+                state: s.state.clone(),
+                lower_non_critical: s.thresholds.lower_non_critical,
+                lower_critical: s.thresholds.lower_critical,
+                lower_non_recoverable: s.thresholds.lower_non_recoverable,
+            })
+            .collect();
+        Fans { fans }
+    }
+}
+
+/// Query of `GET /sel`.
+#[derive(Serialize, Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct SelQuery {
+    /// Only entries logged at or after this time, e.g. `-1h`.
+    #[serde(default)]
+    pub since: Option<Timestamp>,
+    /// Only the most recent entries, at most this many.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Body of `GET /sel`, oldest entry first.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Sel {
+    pub entries: Vec<SelEntry>,
+}
+
+/// Body of `GET /fru`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Fru {
+    pub devices: Vec<FruDevice>,
+}
+
+/// Entry of `GET /admin/tokens/expiring`. The token value is never included.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct TokenExpiry {
+    pub description: Option<String>,
+    pub expires_at: Option<Timestamp>,
+    pub expired: bool,
+}
+
+/// Body of `POST /admin/enrollment-codes`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct EnrollmentCodeMsg {
+    /// Mint a code for an admin token.
+    #[serde(default)]
+    pub admin: bool,
+    /// How long the code can be exchanged, at most `MAX_ENROLLMENT_SECS`.
+    #[serde(default = "default_enrollment_ttl_secs")]
+    pub ttl_secs: u64,
+}
+fn default_enrollment_ttl_secs() -> u64 {
+    3600
+}
+pub const MAX_ENROLLMENT_SECS: u64 = 7 * 24 * 3600;
+
+/// Body of a successful `POST /admin/enrollment-codes`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct EnrollmentCodeResponse {
+    pub code: String,
+    pub expires_at: Timestamp,
+}
+
+/// Body of `POST /auth/enroll`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct EnrollMsg {
+    pub code: String,
+    /// Who the token is for, becomes its description.
+    pub owner: String,
+}
+
+/// Body of a successful `POST /auth/enroll`. The token is not shown again.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EnrollResponse {
+    pub token: String,
+}
+
+/// Body of `POST /auth/delegate`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct DelegateMsg {
+    /// Who the token is for, becomes its description.
+    pub owner: String,
+    /// How long the token is valid, at most `MAX_DELEGATION_SECS`.
+    #[serde(default = "default_delegation_ttl_secs")]
+    pub ttl_secs: u64,
+    /// What the token may do, read-only by default. Cannot be wider than
+    /// the caller's own rights.
+    #[serde(default = "default_delegation_scope")]
+    pub scope: Vec<Action>,
+}
+fn default_delegation_ttl_secs() -> u64 {
+    2 * 3600
+}
+fn default_delegation_scope() -> Vec<Action> {
+    vec![Action::Status, Action::Read]
+}
+pub const MAX_DELEGATION_SECS: u64 = 7 * 24 * 3600;
+
+/// Body of a successful `POST /auth/delegate`. The token is not shown again.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DelegateResponse {
+    pub token: String,
+    pub expires_at: Timestamp,
+    pub scope: Vec<Action>,
+}
+
+/// Query of `GET /auth/can-i`.
+#[derive(Serialize, Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct CanIQuery {
+    /// Named as in a token's `scope`.
+    pub action: Action,
+}
+
+/// Body of `GET /auth/can-i`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CanI {
+    pub allowed: bool,
+    /// Why not, when `allowed` is false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// A `reason` has to be sent along with the action.
+    #[serde(default)]
+    pub reason_required: bool,
+}
+
+/// Query of `GET /admin/tokens/expiring`.
+#[derive(Serialize, Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct ExpiringQuery {
+    #[serde(default = "default_expiring_days")]
+    pub days: u64,
+}
+fn default_expiring_days() -> u64 {
+    30
+}
+
+/// Entry of `GET /admin/access-report`. The token value is never included.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct AccessEntry {
+    pub label: String,
+    /// `config`, `enrolled` or `delegated`.
+    pub source: String,
+    pub admin: bool,
+    /// Can send arbitrary IPMI requests through `POST /raw`.
+    pub raw: bool,
+    pub created_at: Option<Timestamp>,
+    pub expires_at: Option<Timestamp>,
+    pub expired: bool,
+    /// Last authenticated request since startup.
+    pub last_used: Option<Timestamp>,
+    /// Label of the token that delegated this one.
+    pub delegated_by: Option<String>,
+    /// The actions the token is limited to, if any.
+    pub scope: Option<Vec<Action>>,
+}
+
+impl AccessEntry {
+    /// `raw` tells what `/auth/can-i` would answer, taking the scope into
+    /// account.
+    pub fn new(token: &TokenConfig, source: &str, last_used: Option<Timestamp>) -> AccessEntry {
+        AccessEntry {
+            label: token.label(),
+            source: source.to_string(),
+            admin: token.admin,
+            raw: token.allows(Action::Raw),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            expired: token.is_expired(),
+            last_used,
+            delegated_by: token.delegated_by.clone(),
+            scope: token.scope.clone(),
+        }
+    }
+
+    pub const CSV_HEADER: &'static str =
+        "label,source,admin,raw,created_at,expires_at,expired,last_used,delegated_by,scope";
+
+    pub fn csv_row(&self) -> String {
+        let time = |t: &Option<Timestamp>| t.map(|t| t.to_string()).unwrap_or_default();
+        // space separated, as in the JSON
+        let scope = self.scope.as_ref().map(|scope| {
+            scope
+                .iter()
+                .filter_map(|a| serde_json::to_value(a).ok()?.as_str().map(String::from))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        format!(
+            "\"{}\",{},{},{},{},{},{},{},\"{}\",{}",
+            self.label.replace('"', "\"\""),
+            self.source,
+            self.admin,
+            self.raw,
+            time(&self.created_at),
+            time(&self.expires_at),
+            self.expired,
+            time(&self.last_used),
+            self.delegated_by
+                .as_deref()
+                .unwrap_or_default()
+                .replace('"', "\"\""),
+            scope.unwrap_or_default()
+        )
+    }
+}
+
+/// Query of `GET /admin/access-report`.
+#[derive(Serialize, Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct AccessReportQuery {
+    /// `json` (the default) or `csv`.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// Body of `GET /admin/stats`: ipmitool usage since startup.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default, Clone)]
+pub struct CommandStats {
+    pub total: CommandCount,
+    /// Keyed by token label, plus `anonymous` for the unauthenticated
+    /// `GET /power` and `canary` for the background probe.
+    pub by_caller: BTreeMap<String, CommandCount>,
+    pub failures_by_kind: BTreeMap<PowerErrorKind, u64>,
+    /// Requests refused by `load_shedding`.
+    pub shed_requests: u64,
+}
+
+/// Also the body of `GET /metrics/me`, for the calling token.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default, Clone)]
+pub struct CommandCount {
+    pub commands: u64,
+    pub failures: u64,
+    pub total_duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<PowerError>,
+}
+
+impl CommandStats {
+    pub fn record(&mut self, caller: &str, duration: Duration, error: Option<&PowerError>) {
+        let per_caller = self.by_caller.entry(caller.to_string()).or_default();
+        for count in [&mut self.total, per_caller] {
+            count.commands += 1;
+            count.failures += u64::from(error.is_some());
+            count.total_duration_ms += duration.as_millis() as u64;
+            if error.is_some() {
+                count.last_error = error.cloned();
+            }
+        }
+        if let Some(error) = error {
+            *self.failures_by_kind.entry(error.kind).or_default() += 1;
+        }
+    }
+}
+
+/// Body of `GET`/`PUT /admin/kill-switch`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct KillSwitch {
+    pub enabled: bool,
+}
+
+/// Body of `GET`/`PUT /admin/disabled`.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct Disabled {
+    pub disabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Body of `GET /admin/support-bundle`: what a bug report needs, without
+/// secrets.
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SupportBundle {
+    pub version: String,
+    /// The effective config, defaults filled in, see `Config::redacted`.
+    #[schema(value_type = Object)]
+    pub config: Config,
+    #[schema(value_type = Object)]
+    pub state: PersistedState,
+    /// Including the last error of each caller.
+    pub stats: CommandStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_token(scope: Option<Vec<Action>>) -> TokenConfig {
+        TokenConfig {
+            token: "0123456789abcdef0123".to_string(),
+            description: Some("vendor tool".to_string()),
+            created_at: None,
+            expires_at: None,
+            admin: true,
+            raw: true,
+            delegated_by: None,
+            scope,
+        }
+    }
+
+    #[test]
+    fn access_entry_reports_raw_within_the_scope_only() {
+        assert!(AccessEntry::new(&raw_token(None), "config", None).raw);
+        assert!(AccessEntry::new(&raw_token(Some(vec![Action::Raw])), "config", None).raw);
+        let scoped = raw_token(Some(vec![Action::Status, Action::Admin]));
+        let entry = AccessEntry::new(&scoped, "config", None);
+        assert!(entry.admin);
+        assert!(!entry.raw);
+        assert!(entry.csv_row().contains(",config,true,false,"));
+    }
+}
@@ -0,0 +1,295 @@
+//! The BMC itself, as opposed to the server it manages.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// From `ipmitool mc info` and `mc guid`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct BmcInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipmi_version: Option<String>,
+    /// IANA enterprise number, e.g. 10876 for Supermicro.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<u8>,
+    /// Missing on BMCs not supporting `Get Device GUID`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+}
+
+/// See `ipmitool mc reset`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetMode {
+    /// Reboots the BMC.
+    Cold,
+    /// Restarts the BMC firmware without rebooting it, not every BMC
+    /// supports it.
+    Warm,
+}
+
+/// From `ipmitool mc watchdog get`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct Watchdog {
+    pub running: bool,
+    /// Who set the timer, e.g. `SMS/OS` or `BIOS FRB2`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timer_use: Option<String>,
+    /// What happens when it runs out, e.g. `Hard Reset`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_countdown_secs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub present_countdown_secs: Option<u32>,
+}
+
+/// See `ipmitool mc watchdog`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogAction {
+    /// Restart the countdown ("pet" the watchdog).
+    Reset,
+    /// Stop the timer.
+    Off,
+}
+
+/// A user slot of the BMC, from `ipmitool user list`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct BmcUser {
+    pub id: u8,
+    /// Empty for unused slots.
+    pub name: String,
+    pub ipmi_messaging: bool,
+    /// Privilege on the LAN channel, e.g. `ADMINISTRATOR` or `NO ACCESS`.
+    pub privilege: String,
+}
+
+/// Network settings of the BMC, from `ipmitool lan print`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct BmcLan {
+    /// E.g. `Static Address` or `DHCP Address`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subnet_mask: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<String>,
+    /// Missing when VLAN tagging is disabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_id: Option<u16>,
+    /// RMCP+ cipher suites the BMC offers. Suite 0 allows logging in
+    /// without a password.
+    pub cipher_suites: Vec<u8>,
+}
+
+/// Longest password IPMI v2 can store.
+pub const MAX_PASSWORD_LEN: usize = 20;
+
+pub fn info(config: &Config) -> Result<BmcInfo, PowerError> {
+    let output = run(config, &["mc", "info"])?;
+    let field = |key: &str| field_of(&output, key);
+    // optional, a failure here should not hide the rest
+    let guid = run(config, &["mc", "guid"])
+        .ok()
+        .and_then(|output| field_of(&output, "System GUID"));
+    Ok(BmcInfo {
+        firmware_revision: field("Firmware Revision"),
+        ipmi_version: field("IPMI Version"),
+        manufacturer_id: field("Manufacturer ID").and_then(|id| id.parse().ok()),
+        manufacturer_name: field("Manufacturer Name"),
+        product_name: field("Product Name"),
+        device_id: field("Device ID").and_then(|id| id.parse().ok()),
+        guid,
+    })
+}
+
+/// Resets the BMC, the server keeps running. The BMC does not answer until
+/// it is back, usually a minute or two.
+pub fn reset(mode: ResetMode, config: &Config) -> Result<(), PowerError> {
+    let mode = match mode {
+        ResetMode::Cold => "cold",
+        ResetMode::Warm => "warm",
+    };
+    run(config, &["mc", "reset", mode]).map(|_| ())
+}
+
+/// Reads the watchdog timer, `None` if the output has no timer state.
+pub fn watchdog(config: &Config) -> Result<Option<Watchdog>, PowerError> {
+    let output = run(config, &["mc", "watchdog", "get"])?;
+    let field = |key: &str| field_of(&output, key);
+    // names and values carry their code, e.g. `Hard Reset (0x01)`
+    let name = |key: &str| {
+        field(key).map(|v| {
+            v.rsplit_once(" (0x")
+                .map_or(v.as_str(), |(n, _)| n)
+                .to_string()
+        })
+    };
+    let secs = |key: &str| field(key)?.split_whitespace().next()?.parse().ok();
+    let Some(state) = field("Watchdog Timer Is") else {
+        return Ok(None);
+    };
+    Ok(Some(Watchdog {
+        running: state.starts_with("Started"),
+        timer_use: name("Watchdog Timer Use"),
+        action: name("Watchdog Timer Actions"),
+        initial_countdown_secs: secs("Initial Countdown"),
+        present_countdown_secs: secs("Present Countdown"),
+    }))
+}
+
+pub fn watchdog_action(action: WatchdogAction, config: &Config) -> Result<(), PowerError> {
+    let action = match action {
+        WatchdogAction::Reset => "reset",
+        WatchdogAction::Off => "off",
+    };
+    run(config, &["mc", "watchdog", action]).map(|_| ())
+}
+
+pub fn lan(config: &Config) -> Result<BmcLan, PowerError> {
+    let channel = config.ipmi_channel.to_string();
+    let output = run(config, &["lan", "print", &channel])?;
+    let field = |key: &str| field_of(&output, key);
+    Ok(BmcLan {
+        ip_source: field("IP Address Source"),
+        ip_address: field("IP Address"),
+        subnet_mask: field("Subnet Mask"),
+        gateway: field("Default Gateway IP"),
+        mac_address: field("MAC Address"),
+        vlan_id: field("802.1q VLAN ID").and_then(|id| id.parse().ok()),
+        cipher_suites: field("RMCP+ Cipher Suites")
+            .map(|suites| {
+                suites
+                    .split(',')
+                    .filter_map(|s| s.trim().parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+pub fn users(config: &Config) -> Result<Vec<BmcUser>, PowerError> {
+    let channel = config.ipmi_channel.to_string();
+    let output = run(config, &["user", "list", &channel])?;
+    Ok(parse_user_list(&output))
+}
+
+/// Sets the password of user `id`, stored as 20 bytes when longer than 16.
+/// ipmitool only takes it as an argument, so it is briefly visible in ps.
+pub fn set_user_password(id: u8, password: &str, config: &Config) -> Result<(), PowerError> {
+    let id = id.to_string();
+    let mut args = vec!["user", "set", "password", &id, password];
+    if password.len() > 16 {
+        args.push("20");
+    }
+    run(config, &args).map(|_| ()).map_err(|mut e| {
+        e.message = e.message.replace(password, "<redacted>");
+        e
+    })
+}
+
+pub fn set_user_enabled(id: u8, enabled: bool, config: &Config) -> Result<(), PowerError> {
+    let id = id.to_string();
+    let action = if enabled { "enable" } else { "disable" };
+    run(config, &["user", action, &id]).map(|_| ())
+}
+
+/// `user list` prints a fixed-width table, cut along the header, e.g.
+/// `ID  Name             Callin  Link Auth  IPMI Msg   Channel Priv Limit`.
+/// ipmitool aligns the header with tabs and the rows with spaces.
+fn parse_user_list(output: &str) -> Vec<BmcUser> {
+    let mut lines = output.lines();
+    let Some(header) = lines.next().map(expand_tabs) else {
+        return Vec::new();
+    };
+    let (Some(name), Some(callin), Some(msg), Some(privilege)) = (
+        header.find("Name"),
+        header.find("Callin"),
+        header.find("IPMI Msg"),
+        header.find("Channel Priv Limit"),
+    ) else {
+        return Vec::new();
+    };
+    let column = |line: &str, start: usize, end: usize| {
+        let len = line.len();
+        line.get(start.min(len)..end.min(len))
+            .unwrap_or("")
+            .trim()
+            .to_string()
+    };
+    lines
+        .filter_map(|line| {
+            Some(BmcUser {
+                id: column(line, 0, name).parse().ok()?,
+                name: column(line, name, callin),
+                ipmi_messaging: column(line, msg, privilege) == "true",
+                privilege: column(line, privilege, line.len()),
+            })
+        })
+        .collect()
+}
+
+/// Replaces tabs with spaces up to the next multiple of 8 columns, as a
+/// terminal shows them.
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+    for c in line.chars() {
+        if c == '\t' {
+            let width = 8 - expanded.chars().count() % 8;
+            expanded.extend(std::iter::repeat_n(' ', width));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+/// Value of the `key : value` line for `key`, if it has one.
+fn field_of(output: &str, key: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (k, value) = line.split_once(':')?;
+        let value = value.trim();
+        (k.trim() == key && !value.is_empty()).then(|| value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_user_list_reads_captured_output() {
+        let output = "\
+ID  Name\t     Callin  Link Auth\tIPMI Msg   Channel Priv Limit
+1                    true    false      false      Unknown (0x00)
+2   ADMIN            false   false      true       ADMINISTRATOR
+3   operator         true    true       true       OPERATOR
+4                    true    false      false      NO ACCESS
+";
+        let users = parse_user_list(output);
+        assert_eq!(users.len(), 4);
+        assert_eq!(users[0].id, 1);
+        assert_eq!(users[0].name, "");
+        assert_eq!(users[0].privilege, "Unknown (0x00)");
+        assert_eq!(users[1].name, "ADMIN");
+        assert!(users[1].ipmi_messaging);
+        assert_eq!(users[1].privilege, "ADMINISTRATOR");
+        assert_eq!(users[2].name, "operator");
+        assert_eq!(users[3].privilege, "NO ACCESS");
+        assert!(!users[3].ipmi_messaging);
+    }
+}
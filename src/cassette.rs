@@ -0,0 +1,142 @@
+//! Recording and replaying ipmitool invocations ("cassettes"), to reproduce
+//! parsing and error mapping with real BMC output but without the BMC.
+
+use crate::config::Config;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// One ipmitool run. Only the command is kept, not the connection arguments
+/// with the credentials.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interaction {
+    pub interface: String,
+    pub args: Vec<String>,
+    /// `None` when ipmitool was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+enum Mode {
+    Record,
+    /// Interactions already handed out are skipped, so a cassette can hold
+    /// changing answers to the same command.
+    Replay {
+        used: Vec<bool>,
+    },
+}
+
+struct Tape {
+    path: String,
+    mode: Mode,
+    interactions: Vec<Interaction>,
+}
+
+/// A cassette in use, set as `Config::cassette`. Clones of the config share
+/// it, so every ipmitool run of a service goes to the same cassette while
+/// tests can each use their own.
+#[derive(Clone)]
+pub struct Cassette(Arc<Mutex<Tape>>);
+
+impl fmt::Debug for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cassette({})", self.0.lock().unwrap().path)
+    }
+}
+
+impl Cassette {
+    /// Appends every ipmitool run to the cassette at `path`, starting it
+    /// afresh.
+    pub fn record(path: &str) -> Cassette {
+        Cassette::new(Tape {
+            path: path.to_string(),
+            mode: Mode::Record,
+            interactions: Vec::new(),
+        })
+    }
+
+    /// Answers ipmitool runs from the cassette at `path` instead of running
+    /// it.
+    pub fn replay(path: &str) -> anyhow::Result<Cassette> {
+        let interactions: Vec<Interaction> = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+        info!(
+            "Replaying {} ipmitool interactions from {}",
+            interactions.len(),
+            path
+        );
+        Ok(Cassette::new(Tape {
+            path: path.to_string(),
+            mode: Mode::Replay {
+                used: vec![false; interactions.len()],
+            },
+            interactions,
+        }))
+    }
+
+    fn new(tape: Tape) -> Cassette {
+        Cassette(Arc::new(Mutex::new(tape)))
+    }
+
+    /// The recorded answer to this run when replaying. The next unused
+    /// matching interaction is taken, or the last matching one once all are
+    /// used. A run missing from the cassette fails like ipmitool could not be
+    /// started.
+    pub fn replayed(&self, interface: &str, args: &[&str]) -> Option<Result<Interaction, String>> {
+        let mut tape = self.0.lock().unwrap();
+        let tape = &mut *tape;
+        let Mode::Replay { used } = &mut tape.mode else {
+            return None;
+        };
+        let matching: Vec<usize> = (0..tape.interactions.len())
+            .filter(|&i| {
+                let recorded = &tape.interactions[i];
+                recorded.interface == interface && recorded.args == args
+            })
+            .collect();
+        let Some(&last) = matching.last() else {
+            warn!("No recorded interaction for ipmitool {}", args.join(" "));
+            return Some(Err(format!(
+                "no recorded interaction for {}",
+                args.join(" ")
+            )));
+        };
+        let i = matching.into_iter().find(|&i| !used[i]).unwrap_or(last);
+        used[i] = true;
+        Some(Ok(tape.interactions[i].clone()))
+    }
+
+    /// Adds a run to the cassette when recording, with the BMC credentials
+    /// and passwords being set removed, and writes it out.
+    pub fn record_interaction(&self, mut interaction: Interaction, config: &Config) {
+        let mut cassette = self.0.lock().unwrap();
+        if !matches!(cassette.mode, Mode::Record) {
+            return;
+        }
+        if interaction
+            .args
+            .starts_with(&["user", "set", "password"].map(String::from))
+        {
+            if let Some(password) = interaction.args.get_mut(4) {
+                *password = "<redacted>".to_string();
+            }
+        }
+        for secret in std::iter::once(&config.password).chain(&config.kg_key) {
+            if !secret.is_empty() {
+                interaction.stdout = interaction.stdout.replace(secret.as_str(), "<redacted>");
+                interaction.stderr = interaction.stderr.replace(secret.as_str(), "<redacted>");
+            }
+        }
+        cassette.interactions.push(interaction);
+        // write and rename so a crash never leaves a truncated file behind
+        let tmp = format!("{}.tmp", cassette.path);
+        let written = serde_yaml::to_string(&cassette.interactions)
+            .map_err(anyhow::Error::from)
+            .and_then(|yaml| Ok(std::fs::write(&tmp, yaml)?))
+            .and_then(|()| Ok(std::fs::rename(&tmp, &cassette.path)?));
+        if let Err(e) = written {
+            warn!("Failed to write cassette {}: {}", cassette.path, e);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! The full `ipmitool chassis status`, beyond the power state.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ChassisStatus {
+    pub power_on: bool,
+    pub power_overload: bool,
+    pub power_interlock: bool,
+    pub main_power_fault: bool,
+    pub power_control_fault: bool,
+    /// `always-on`, `always-off` or `previous`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_restore_policy: Option<String>,
+    /// What caused the last power change, e.g. `command`, missing when the
+    /// BMC does not know.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_power_event: Option<String>,
+    pub intrusion: bool,
+    pub front_panel_lockout: bool,
+    pub drive_fault: bool,
+    pub cooling_fault: bool,
+    /// Every field as printed, including vendor additions.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// From `ipmitool chassis poh`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+pub struct PowerOnHours {
+    pub hours: u64,
+}
+
+/// What the server does when mains power comes back, see `ipmitool chassis
+/// policy`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestorePolicy {
+    AlwaysOn,
+    AlwaysOff,
+    /// Back to the state before the outage.
+    Previous,
+}
+
+/// Reads the chassis status, `None` if the output has no power state.
+pub fn status(config: &Config) -> Result<Option<ChassisStatus>, PowerError> {
+    let output = run(config, &["chassis", "status"])?;
+    let fields: BTreeMap<String, String> = output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let field = |key: &str| fields.get(key).filter(|v| !v.is_empty()).cloned();
+    // flags are `true`/`false` or `active`/`inactive`
+    let flag = |key: &str| matches!(fields.get(key).map(String::as_str), Some("true" | "active"));
+    let Some(power) = field("System Power") else {
+        return Ok(None);
+    };
+    Ok(Some(ChassisStatus {
+        power_on: power == "on",
+        power_overload: flag("Power Overload"),
+        power_interlock: flag("Power Interlock"),
+        main_power_fault: flag("Main Power Fault"),
+        power_control_fault: flag("Power Control Fault"),
+        power_restore_policy: field("Power Restore Policy"),
+        last_power_event: field("Last Power Event"),
+        intrusion: flag("Chassis Intrusion"),
+        front_panel_lockout: flag("Front-Panel Lockout"),
+        drive_fault: flag("Drive Fault"),
+        cooling_fault: flag("Cooling/Fan Fault"),
+        fields,
+    }))
+}
+
+pub fn set_restore_policy(policy: RestorePolicy, config: &Config) -> Result<(), PowerError> {
+    let policy = match policy {
+        RestorePolicy::AlwaysOn => "always-on",
+        RestorePolicy::AlwaysOff => "always-off",
+        RestorePolicy::Previous => "previous",
+    };
+    run(config, &["chassis", "policy", policy]).map(|_| ())
+}
+
+/// Reads the power-on hours counter, `None` if the output is not understood.
+pub fn power_on_hours(config: &Config) -> Result<Option<PowerOnHours>, PowerError> {
+    let output = run(config, &["chassis", "poh"])?;
+    Ok(output
+        .lines()
+        .find_map(|line| line.strip_prefix("POH Counter"))
+        .and_then(|line| line.split_once(':'))
+        .and_then(|(_, value)| parse_poh(value.trim()))
+        .map(|hours| PowerOnHours { hours }))
+}
+
+/// `29856 hours total (1244 days, 0 hours)` in current ipmitool, `1244
+/// days, 0 hours` in older ones.
+fn parse_poh(value: &str) -> Option<u64> {
+    if let Some((hours, _)) = value.split_once(" hours total") {
+        return hours.trim().parse().ok();
+    }
+    let (days, hours) = value.split_once(',')?;
+    let days: u64 = days.trim().strip_suffix("days")?.trim().parse().ok()?;
+    let hours: u64 = hours.trim().strip_suffix("hours")?.trim().parse().ok()?;
+    Some(days * 24 + hours)
+}
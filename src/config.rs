@@ -0,0 +1,695 @@
+use crate::cassette::Cassette;
+use crate::ipmi::BootOptions;
+use crate::oem::Vendor;
+use crate::timestamp::Timestamp;
+use axum::http::{HeaderName, HeaderValue};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    pub ipmi_address: String,
+    pub username: String,
+    pub password: String,
+    /// BMC key for BMCs requiring one, passed to ipmitool via `IPMI_KGKEY`.
+    #[serde(default)]
+    pub kg_key: Option<String>,
+    /// Retry with IPMI v1.5 (`-I lan`) when the BMC does not support lanplus.
+    #[serde(default)]
+    pub lan_fallback: bool,
+    /// LAN channel of the BMC, for commands taking one such as `user list`.
+    #[serde(default = "default_ipmi_channel")]
+    pub ipmi_channel: u8,
+    pub listen_port: u16,
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub canary: Option<CanaryConfig>,
+    /// Reject request bodies carrying fields this version does not know.
+    #[serde(default)]
+    pub strict_requests: bool,
+    /// Refuse destructive power actions and BMC resets without a `reason`.
+    #[serde(default)]
+    pub require_reason: bool,
+    #[serde(default)]
+    pub expected_state: ExpectedState,
+    /// How long after an on/off request readings still disagreeing with it
+    /// are reported as `powering_on`/`powering_off`.
+    #[serde(default = "default_transition_window_secs")]
+    pub transition_window_secs: u64,
+    /// Refuse every destructive action. Unlike the runtime switch this
+    /// cannot be released through the admin API.
+    #[serde(default)]
+    pub kill_switch: bool,
+    /// Take the BMC out of service: every request touching it returns 410
+    /// with `disabled_reason`. Unlike the runtime flag this cannot be cleared
+    /// through the admin API.
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub disabled_reason: Option<String>,
+    /// This instance serves cluster fencing agents: short ipmitool timeouts,
+    /// and `off` only succeeds once a status reading confirms it.
+    #[serde(default)]
+    pub fencing: bool,
+    /// Only report on/off as successful once a status reading confirms them.
+    #[serde(default)]
+    pub confirm_by_status: Option<ConfirmConfig>,
+    /// Longest ipmitool output returned to clients, e.g. in error messages.
+    #[serde(default = "default_output_limit_bytes")]
+    pub output_limit_bytes: usize,
+    /// Refuse to start while `lint` reports findings.
+    #[serde(default)]
+    pub strict_security: bool,
+    /// Where runtime state such as the kill switch is kept across restarts.
+    #[serde(default)]
+    pub state_file: Option<String>,
+    /// Boot options used when `POST /bootdev` does not give them.
+    #[serde(default)]
+    pub bootdev: BootOptions,
+    /// Serial-over-LAN consoles without traffic either way are closed after
+    /// this long.
+    #[serde(default = "default_console_idle_timeout_secs")]
+    pub console_idle_timeout_secs: u64,
+    /// Command run instead of `ipmitool power status`, for hardware whose
+    /// status needs a vendor tool. See `check_status_command`.
+    #[serde(default)]
+    pub status_command: Option<Vec<String>>,
+    /// Management networks the BMC has to be in, e.g. `10.20.0.0/16`. When
+    /// set, an `ipmi_address` outside them is refused at startup.
+    #[serde(default)]
+    pub allowed_networks: Vec<Network>,
+    /// Compress responses with gzip or brotli when the client accepts it.
+    #[serde(default)]
+    pub compression: bool,
+    /// Enables the curated OEM commands of this vendor under `POST /oem`.
+    #[serde(default)]
+    pub vendor: Option<Vendor>,
+    /// Refuse low-priority requests while the service is saturated.
+    #[serde(default)]
+    pub load_shedding: Option<LoadShedding>,
+    /// Headers added to every response, e.g. `Strict-Transport-Security`.
+    /// They replace a header of the same name set by a handler.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Set from `--record` or `--replay`, never read from the file.
+    #[serde(skip)]
+    pub cassette: Option<Cassette>,
+}
+fn default_ipmi_channel() -> u8 {
+    1
+}
+fn default_transition_window_secs() -> u64 {
+    60
+}
+fn default_output_limit_bytes() -> usize {
+    512
+}
+fn default_console_idle_timeout_secs() -> u64 {
+    600
+}
+
+/// The state the server is normally supposed to be in. A different reading is
+/// reported as drift but never corrected automatically.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectedState {
+    On,
+    Off,
+    #[default]
+    Any,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfirmConfig {
+    pub timeout_secs: u64,
+    pub interval_secs: u64,
+}
+impl Default for ConfirmConfig {
+    fn default() -> Self {
+        ConfirmConfig {
+            timeout_secs: 20,
+            interval_secs: 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CanaryConfig {
+    pub interval_secs: u64,
+    pub failure_threshold: u32,
+}
+
+/// Status polls and listings (GET requests) are answered with 503 while more
+/// than `max_in_flight` requests are being handled, keeping room for power
+/// control. `/readyz` and the admin routes are never refused.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoadShedding {
+    pub max_in_flight: usize,
+    #[serde(default = "default_retry_after_secs")]
+    pub retry_after_secs: u64,
+}
+fn default_retry_after_secs() -> u64 {
+    5
+}
+
+/// A network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`. A bare
+/// address is a network of one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid network {}: {}", s, e))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| anyhow::anyhow!("Invalid prefix length in {}", s))?,
+        };
+        Ok(Network { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<Network> for String {
+    fn from(network: Network) -> Self {
+        network.to_string()
+    }
+}
+
+/// A bearer token, written either as a bare string or as a map carrying
+/// bookkeeping fields.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "TokenEntry")]
+pub struct TokenConfig {
+    pub token: String,
+    pub description: Option<String>,
+    pub created_at: Option<Timestamp>,
+    pub expires_at: Option<Timestamp>,
+    /// Allows the `/admin` routes.
+    pub admin: bool,
+    /// Allows `POST /raw`, on top of `admin`.
+    pub raw: bool,
+    /// Label of the token that minted this one with `POST /auth/delegate`.
+    pub delegated_by: Option<String>,
+    /// Limits the token to these actions, on top of `admin` and `raw`.
+    /// Without one the token may do everything those allow.
+    pub scope: Option<Vec<Action>>,
+}
+
+/// What a token can be allowed to do, see `TokenConfig::allows`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// `GET /power`.
+    Status,
+    On,
+    Off,
+    Soft,
+    Diag,
+    /// Every other request only reading from the BMC, e.g. sensors, SEL and
+    /// FRU.
+    Read,
+    Identify,
+    Bootdev,
+    SelClear,
+    Console,
+    BmcReset,
+    Watchdog,
+    PowerCap,
+    RestorePolicy,
+    /// The OEM commands not requiring an admin token.
+    Oem,
+    /// The OEM commands changing BMC settings.
+    OemAdmin,
+    SensorThresholds,
+    BmcUsers,
+    Pef,
+    Raw,
+    Delegate,
+    /// The `/admin` routes.
+    Admin,
+}
+
+impl Action {
+    pub fn needs_admin(self) -> bool {
+        matches!(
+            self,
+            Action::OemAdmin
+                | Action::SensorThresholds
+                | Action::BmcUsers
+                | Action::Pef
+                | Action::Raw
+                | Action::Admin
+        )
+    }
+    /// Refused while the kill switch is engaged.
+    pub fn is_destructive(self) -> bool {
+        matches!(
+            self,
+            Action::Off
+                | Action::Soft
+                | Action::Diag
+                | Action::SelClear
+                | Action::PowerCap
+                | Action::RestorePolicy
+                | Action::Raw
+                | Action::BmcReset
+        )
+    }
+    /// Refused without a `reason` when `require_reason` is set.
+    pub fn needs_reason(self) -> bool {
+        matches!(
+            self,
+            Action::Off | Action::Soft | Action::Diag | Action::BmcReset
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenEntry {
+    Plain(String),
+    Detailed {
+        token: String,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        created_at: Option<Timestamp>,
+        #[serde(default)]
+        expires_at: Option<Timestamp>,
+        #[serde(default)]
+        admin: bool,
+        #[serde(default)]
+        raw: bool,
+        #[serde(default)]
+        delegated_by: Option<String>,
+        #[serde(default)]
+        scope: Option<Vec<Action>>,
+    },
+}
+
+impl From<TokenEntry> for TokenConfig {
+    fn from(entry: TokenEntry) -> Self {
+        match entry {
+            TokenEntry::Plain(token) => TokenConfig {
+                token,
+                description: None,
+                created_at: None,
+                expires_at: None,
+                admin: false,
+                raw: false,
+                delegated_by: None,
+                scope: None,
+            },
+            TokenEntry::Detailed {
+                token,
+                description,
+                created_at,
+                expires_at,
+                admin,
+                raw,
+                delegated_by,
+                scope,
+            } => TokenConfig {
+                token,
+                description,
+                created_at,
+                expires_at,
+                admin,
+                raw,
+                delegated_by,
+                scope,
+            },
+        }
+    }
+}
+
+impl TokenConfig {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at.is_past())
+    }
+    pub fn expires_within(&self, window: Duration) -> bool {
+        self.expires_at.is_some_and(|at| at.is_within(window))
+    }
+    /// Whether the token may do `action`: `admin` and `raw` allow it, and
+    /// the scope if it has one lists it.
+    pub fn allows(&self, action: Action) -> bool {
+        self.has_role_for(action) && self.has_in_scope(action)
+    }
+    pub fn has_role_for(&self, action: Action) -> bool {
+        match action {
+            Action::Raw => self.admin && self.raw,
+            action => !action.needs_admin() || self.admin,
+        }
+    }
+    pub fn has_in_scope(&self, action: Action) -> bool {
+        self.scope
+            .as_ref()
+            .is_none_or(|scope| scope.contains(&action))
+    }
+    /// Name to use in logs and stats: the description followed by `id`, so
+    /// tokens sharing a description are still told apart.
+    pub fn label(&self) -> String {
+        match &self.description {
+            Some(description) => format!("{} ({})", description, self.id()),
+            None => self.id(),
+        }
+    }
+    /// Stable short name derived from the token (FNV-1a, cut to 32 bits),
+    /// which does not give away any of its characters.
+    pub fn id(&self) -> String {
+        let hash = self.token.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+        });
+        format!("token-{:08x}", hash >> 32)
+    }
+}
+
+impl Config {
+    pub fn from_yaml_file(file: &str) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(file)?;
+        let reader = std::io::BufReader::new(file);
+        let mut config: Config = serde_yaml::from_reader(reader)?;
+        config.ipmi_address = normalize_ipmi_address(&config.ipmi_address)?;
+        config.check_allowed_networks()?;
+        config.check_status_command()?;
+        config.response_headers()?;
+        Ok(config)
+    }
+    /// The configured `headers`, refusing names or values HTTP does not allow.
+    pub fn response_headers(&self) -> anyhow::Result<Vec<(HeaderName, HeaderValue)>> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_str(name)
+                    .map_err(|_| anyhow::anyhow!("Invalid header name {:?}", name))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| anyhow::anyhow!("Invalid value for header {}", name))?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+    /// A `status_command` is an argv run without a shell, starting with an
+    /// absolute path since it gets no `PATH`. Arguments may only use the
+    /// `{address}` and `{username}` placeholders, the password is passed in
+    /// `IPMI_PASSWORD` so it does not show up in ps.
+    fn check_status_command(&self) -> anyhow::Result<()> {
+        let Some(argv) = &self.status_command else {
+            return Ok(());
+        };
+        let Some(program) = argv.first() else {
+            anyhow::bail!("status_command is empty");
+        };
+        if !std::path::Path::new(program).is_absolute() {
+            anyhow::bail!("status_command has to start with an absolute path");
+        }
+        for arg in argv {
+            let rest = arg.replace("{address}", "").replace("{username}", "");
+            if rest.contains('{') || rest.contains('}') {
+                anyhow::bail!("Unknown placeholder in status_command argument {}", arg);
+            }
+        }
+        Ok(())
+    }
+    /// Refuses a BMC outside `allowed_networks`. Hostnames are resolved once,
+    /// every address they resolve to has to be allowed.
+    fn check_allowed_networks(&self) -> anyhow::Result<()> {
+        if self.allowed_networks.is_empty() {
+            return Ok(());
+        }
+        let host = self
+            .ipmi_address
+            .split_once('%')
+            .map_or(self.ipmi_address.as_str(), |(ip, _zone)| ip);
+        let addrs: Vec<IpAddr> = match host.parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => (host, 0)
+                .to_socket_addrs()
+                .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", host, e))?
+                .map(|addr| addr.ip())
+                .collect(),
+        };
+        for ip in addrs {
+            if !self.allowed_networks.iter().any(|n| n.contains(ip)) {
+                anyhow::bail!(
+                    "BMC address {} ({}) is outside allowed_networks",
+                    self.ipmi_address,
+                    ip
+                );
+            }
+        }
+        Ok(())
+    }
+    /// A copy safe to hand out, with the BMC credentials and token values
+    /// replaced.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.password = "<redacted>".to_string();
+        config.kg_key = config.kg_key.map(|_| "<redacted>".to_string());
+        for token in &mut config.tokens {
+            token.token = "<redacted>".to_string();
+        }
+        config
+    }
+    pub fn find_token(&self, token: &str) -> Option<&TokenConfig> {
+        let entry = self.tokens.iter().find(|t| t.token == token)?;
+        if entry.is_expired() {
+            warn!("Rejecting expired token {}", entry.label());
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+const MIN_TOKEN_LEN: usize = 16;
+
+/// Tokens from the README and example config, which end up copied verbatim.
+const PUBLISHED_TOKENS: &[&str] = &[
+    "a_very_secure_token",
+    "your-secret-token",
+    "another-secret-token",
+];
+
+/// Factory credentials of common BMC vendors.
+const DEFAULT_BMC_CREDENTIALS: &[(&str, &str)] = &[
+    ("ADMIN", "ADMIN"),
+    ("root", "calvin"),
+    ("root", "0penBmc"),
+    ("USERID", "PASSW0RD"),
+    ("admin", "admin"),
+    ("admin", "password"),
+    ("root", "root"),
+];
+
+impl Config {
+    /// Security problems in the config, as human readable findings.
+    pub fn lint(&self) -> Vec<String> {
+        let mut findings = Vec::new();
+        for token in &self.tokens {
+            if token.token.len() < MIN_TOKEN_LEN {
+                findings.push(format!(
+                    "Token {} is shorter than {} characters",
+                    token.label(),
+                    MIN_TOKEN_LEN
+                ));
+            } else if PUBLISHED_TOKENS.contains(&token.token.as_str())
+                || token.token.chars().all(|c| token.token.starts_with(c))
+            {
+                findings.push(format!("Token {} is easy to guess", token.label()));
+            }
+        }
+        let mut descriptions = std::collections::BTreeSet::new();
+        for description in self.tokens.iter().filter_map(|t| t.description.as_ref()) {
+            if !descriptions.insert(description) {
+                findings.push(format!(
+                    "Several tokens are described as {:?}, only their id tells them apart in logs and stats",
+                    description
+                ));
+            }
+        }
+        if DEFAULT_BMC_CREDENTIALS
+            .iter()
+            .any(|(user, password)| self.username == *user && self.password == *password)
+        {
+            findings.push(format!(
+                "BMC user {} still has the vendor default password",
+                self.username
+            ));
+        }
+        findings
+    }
+}
+
+/// Accepts hostnames, IPv4 and IPv6 literals (optionally in brackets and with a
+/// zone ID such as `fe80::1%eth0`) and returns the bare form ipmitool expects.
+fn normalize_ipmi_address(address: &str) -> anyhow::Result<String> {
+    let address = address.trim();
+    let bare = match address.strip_prefix('[') {
+        Some(rest) => rest
+            .strip_suffix(']')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated IPv6 literal: {}", address))?,
+        None => address,
+    };
+    if bare.contains(':') {
+        let ip = bare.split_once('%').map_or(bare, |(ip, _zone)| ip);
+        ip.parse::<std::net::Ipv6Addr>()
+            .map_err(|e| anyhow::anyhow!("Invalid IPv6 address {}: {}", bare, e))?;
+    }
+    Ok(bare.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(s: &str) -> Network {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn network_zero_prefix_contains_its_whole_family() {
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+        assert!(!network("0.0.0.0/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn network_full_prefix_contains_one_address() {
+        assert!(network("10.20.0.5/32").contains(ip("10.20.0.5")));
+        assert!(!network("10.20.0.5/32").contains(ip("10.20.0.6")));
+        assert!(network("fd00:20::5/128").contains(ip("fd00:20::5")));
+        assert!(!network("fd00:20::5/128").contains(ip("fd00:20::6")));
+        // a bare address is a network of one
+        assert!(!network("10.20.0.5").contains(ip("10.20.0.6")));
+    }
+
+    #[test]
+    fn network_refuses_prefixes_wider_than_the_address() {
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("fd00::/129".parse::<Network>().is_err());
+        assert!("10.0.0.0/".parse::<Network>().is_err());
+        assert!("10.0.0.0/-1".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn network_ignores_host_bits() {
+        let net = network("10.20.0.5/16");
+        assert!(net.contains(ip("10.20.99.1")));
+        assert!(!net.contains(ip("10.21.0.5")));
+    }
+
+    #[test]
+    fn network_matches_ipv4_mapped_clients() {
+        let net = network("10.20.0.0/16");
+        assert!(net.contains(ip("::ffff:10.20.0.5")));
+        assert!(!net.contains(ip("::ffff:10.21.0.5")));
+    }
+
+    #[test]
+    fn normalize_ipmi_address_keeps_ipv4_and_hostnames() {
+        assert_eq!(normalize_ipmi_address("10.20.0.5").unwrap(), "10.20.0.5");
+        assert_eq!(
+            normalize_ipmi_address(" bmc01.example.com ").unwrap(),
+            "bmc01.example.com"
+        );
+    }
+
+    #[test]
+    fn normalize_ipmi_address_strips_ipv6_brackets() {
+        assert_eq!(normalize_ipmi_address("fd00:20::5").unwrap(), "fd00:20::5");
+        assert_eq!(
+            normalize_ipmi_address("[fd00:20::5]").unwrap(),
+            "fd00:20::5"
+        );
+    }
+
+    #[test]
+    fn normalize_ipmi_address_keeps_the_zone() {
+        assert_eq!(
+            normalize_ipmi_address("fe80::1%eth0").unwrap(),
+            "fe80::1%eth0"
+        );
+        assert_eq!(
+            normalize_ipmi_address("[fe80::1%eth0]").unwrap(),
+            "fe80::1%eth0"
+        );
+    }
+
+    #[test]
+    fn normalize_ipmi_address_refuses_invalid_input() {
+        assert!(normalize_ipmi_address("[fd00:20::5").is_err());
+        assert!(normalize_ipmi_address("fd00:20::zz").is_err());
+        assert!(normalize_ipmi_address("[fe80::1%eth0").is_err());
+        assert!(normalize_ipmi_address("fe80:::1%eth0").is_err());
+    }
+
+    /// Every action needing a reason is held back by the kill switch too, so
+    /// `/auth/can-i` and the handlers agree.
+    #[test]
+    fn actions_needing_a_reason_are_destructive() {
+        for action in [Action::Off, Action::Soft, Action::Diag, Action::BmcReset] {
+            assert!(action.needs_reason());
+            assert!(action.is_destructive());
+        }
+        assert!(!Action::On.needs_reason());
+    }
+}
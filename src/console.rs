@@ -0,0 +1,75 @@
+//! Serial-over-LAN console bridged to a WebSocket: bytes from the client go to
+//! `ipmitool sol activate`, its output goes back as binary messages.
+
+use crate::config::Config;
+use crate::ipmi;
+use axum::extract::ws::{Message, WebSocket};
+use log::{error, info, warn};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Runs the console until the client disconnects, ipmitool exits or nothing
+/// was sent either way for `idle_timeout`, then deactivates SOL on the BMC.
+pub async fn bridge(mut socket: WebSocket, config: Config, idle_timeout: Duration) {
+    // SOL needs IPMI v2, there is no point in the lan fallback
+    let mut command = tokio::process::Command::from(ipmi::command(&config, "lanplus"));
+    let child = command
+        .args(["sol", "activate"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to run ipmitool: {}", e);
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    let (Some(mut stdin), Some(mut stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        unreachable!("stdin and stdout are piped");
+    };
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Binary(data))) => {
+                    if stdin.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Text(text))) => {
+                    if stdin.write_all(text.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+            read = stdout.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if socket.send(Message::Binary(buf[..n].to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            _ = tokio::time::sleep(idle_timeout) => {
+                warn!("Closing console idle for {:?}", idle_timeout);
+                break;
+            }
+        }
+    }
+    let _ = child.kill().await;
+    let _ = socket.send(Message::Close(None)).await;
+    // the BMC keeps the session otherwise, refusing the next activate
+    let deactivated =
+        tokio::task::spawn_blocking(move || ipmi::run(&config, &["sol", "deactivate"])).await;
+    if let Ok(Err(e)) = deactivated {
+        // usually ipmitool already did on exit
+        info!("SOL not deactivated: {}", e.message);
+    }
+}
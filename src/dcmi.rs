@@ -0,0 +1,115 @@
+//! DCMI power management, from `ipmitool dcmi power`.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// From `ipmitool dcmi power reading`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PowerReading {
+    pub instantaneous_watts: u32,
+    /// Over the sampling period.
+    pub minimum_watts: Option<u32>,
+    pub maximum_watts: Option<u32>,
+    pub average_watts: Option<u32>,
+    pub sampling_period_secs: Option<u64>,
+    /// Whether the BMC is collecting readings at all.
+    pub active: bool,
+}
+
+/// Reads the power draw, `None` if the output has no instantaneous reading.
+pub fn power_reading(config: &Config) -> Result<Option<PowerReading>, PowerError> {
+    let output = run(config, &["dcmi", "power", "reading"])?;
+    let fields = fields(&output);
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    // the value comes first, e.g. `120 Watts` or `00000005 Seconds.`
+    let number = |key: &str| field(key)?.split_whitespace().next()?.parse().ok();
+    let Some(instantaneous_watts) = number("Instantaneous power reading") else {
+        return Ok(None);
+    };
+    Ok(Some(PowerReading {
+        instantaneous_watts,
+        minimum_watts: number("Minimum during sampling period"),
+        maximum_watts: number("Maximum during sampling period"),
+        average_watts: number("Average power reading over sample period"),
+        sampling_period_secs: field("Sampling period")
+            .and_then(|v| v.split_whitespace().next()?.parse().ok()),
+        active: field("Power reading state is") == Some("activated"),
+    }))
+}
+
+/// From `ipmitool dcmi power get_limit`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PowerCap {
+    /// The limit is enforced, not just stored.
+    pub active: bool,
+    pub limit_watts: Option<u32>,
+    /// What the BMC does when the limit cannot be held within the correction
+    /// time, e.g. `Hard Power Off & Log Event to SEL`.
+    pub exception_action: Option<String>,
+    pub correction_time_ms: Option<u32>,
+    pub sampling_period_secs: Option<u32>,
+}
+
+/// Changes to the power cap, fields left out stay as they are.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct PowerCapChange {
+    #[serde(default)]
+    pub limit_watts: Option<u32>,
+    #[serde(default)]
+    pub correction_time_ms: Option<u32>,
+    #[serde(default)]
+    pub sampling_period_secs: Option<u32>,
+    /// Activate or deactivate the limit, after setting the other fields.
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+/// Reads the power cap, `None` if the output has no limit state.
+pub fn power_cap(config: &Config) -> Result<Option<PowerCap>, PowerError> {
+    let output = run(config, &["dcmi", "power", "get_limit"])?;
+    let fields = fields(&output);
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    let number = |key: &str| field(key)?.split_whitespace().next()?.parse().ok();
+    let Some(state) = field("Current Limit State") else {
+        return Ok(None);
+    };
+    Ok(Some(PowerCap {
+        active: !state.starts_with("No Active"),
+        limit_watts: number("Power Limit"),
+        exception_action: field("Exception actions").map(str::to_string),
+        correction_time_ms: number("Correction time"),
+        sampling_period_secs: number("Sampling period"),
+    }))
+}
+
+/// Applies `change` one setting at a time, as ipmitool does not take them
+/// together. Stops at the first failure, earlier settings stay applied.
+pub fn set_power_cap(change: &PowerCapChange, config: &Config) -> Result<(), PowerError> {
+    let settings = [
+        ("limit", change.limit_watts),
+        ("correction", change.correction_time_ms),
+        ("sample", change.sampling_period_secs),
+    ];
+    for (setting, value) in settings {
+        if let Some(value) = value {
+            let value = value.to_string();
+            run(config, &["dcmi", "power", "set_limit", setting, &value])?;
+        }
+    }
+    match change.active {
+        Some(true) => run(config, &["dcmi", "power", "activate"]).map(|_| ()),
+        Some(false) => run(config, &["dcmi", "power", "deactivate"]).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// The `key: value` lines of ipmitool's DCMI output.
+fn fields(output: &str) -> Vec<(&str, &str)> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect()
+}
@@ -0,0 +1,137 @@
+//! Step-by-step reachability check of the BMC, telling network problems from
+//! credential problems from a BMC that stopped working.
+
+use crate::config::Config;
+use crate::ipmi::{PowerError, PowerErrorKind};
+use crate::rmcp;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default)]
+pub struct DiagReport {
+    /// Every step succeeded.
+    pub ok: bool,
+    pub steps: Vec<DiagStep>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct DiagStep {
+    /// `resolve`, `rmcp_ping`, `session` or `chassis_status`.
+    pub step: String,
+    pub outcome: Outcome,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Why the ipmitool session failed, `auth_failed` pointing at the
+    /// credentials and `session_timeout` at the network or BMC.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<PowerError>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    /// An earlier step failed in a way that makes this one pointless.
+    Skipped,
+}
+
+impl DiagReport {
+    fn push(&mut self, step: &str, started: Instant, result: Result<String, String>) {
+        let (outcome, detail) = match result {
+            Ok(detail) => (Outcome::Ok, detail),
+            Err(detail) => (Outcome::Failed, detail),
+        };
+        self.steps.push(DiagStep {
+            step: step.to_string(),
+            outcome,
+            duration_ms: started.elapsed().as_millis() as u64,
+            detail: Some(detail),
+            error: None,
+        });
+    }
+    fn skip(&mut self, step: &str) {
+        self.steps.push(DiagStep {
+            step: step.to_string(),
+            outcome: Outcome::Skipped,
+            duration_ms: 0,
+            detail: None,
+            error: None,
+        });
+    }
+}
+
+/// Resolves the BMC address, pings it over RMCP (UDP 623) and opens an IPMI
+/// session with `chassis_status`, which runs `ipmitool chassis status` once
+/// awaited.
+/// Steps after a failed one still run where they can tell something new,
+/// e.g. some BMCs do not answer presence pings but accept sessions.
+pub async fn diagnose(
+    config: &Config,
+    chassis_status: impl Future<Output = Result<String, PowerError>>,
+) -> DiagReport {
+    let mut report = DiagReport::default();
+
+    let started = Instant::now();
+    let resolved = tokio::net::lookup_host((config.ipmi_address.as_str(), 623))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|mut addrs| {
+            addrs
+                .next()
+                .map(|addr| format!("resolved to {}", addr.ip()))
+                .ok_or_else(|| "no address found".to_string())
+        });
+    let resolve_failed = resolved.is_err();
+    report.push("resolve", started, resolved);
+    if resolve_failed {
+        report.skip("rmcp_ping");
+        report.skip("session");
+        report.skip("chassis_status");
+        return report;
+    }
+
+    let started = Instant::now();
+    let ping = rmcp::ping(&config.ipmi_address)
+        .await
+        .map(|()| "presence pong received".to_string())
+        .map_err(|e| e.to_string());
+    report.push("rmcp_ping", started, ping);
+
+    let started = Instant::now();
+    match chassis_status.await {
+        Ok(output) => {
+            report.push("session", started, Ok("session established".to_string()));
+            let power = output.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "System Power").then(|| value.trim().to_string())
+            });
+            let status = match power {
+                Some(power) => Ok(format!("system power is {}", power)),
+                None => Err("no system power state in chassis status".to_string()),
+            };
+            report.push("chassis_status", started, status);
+        }
+        Err(error) => {
+            let detail = match error.kind {
+                PowerErrorKind::AuthFailed | PowerErrorKind::KgMismatch => {
+                    "BMC answered but refused the credentials"
+                }
+                PowerErrorKind::InsufficientPrivilege => "credentials lack the privilege needed",
+                PowerErrorKind::SessionTimeout => "no session could be established",
+                _ => "ipmitool failed",
+            };
+            report.push("session", started, Err(detail.to_string()));
+            if let Some(step) = report.steps.last_mut() {
+                step.error = Some(error);
+            }
+            report.skip("chassis_status");
+        }
+    }
+
+    report.ok = report.steps.iter().all(|s| s.outcome == Outcome::Ok);
+    report
+}
@@ -0,0 +1,82 @@
+//! FRU (field replaceable unit) inventory from `ipmitool fru print`.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct FruDevice {
+    /// E.g. `Builtin FRU Device`.
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u8>,
+    /// From the product area, falling back to the board.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_tag: Option<String>,
+    /// Every field as printed, e.g. `Board Mfg Date` or `Chassis Type`.
+    pub fields: BTreeMap<String, String>,
+}
+
+pub fn inventory(config: &Config) -> Result<Vec<FruDevice>, PowerError> {
+    let output = run(config, &["fru", "print"])?;
+    Ok(parse_fru_print(&output))
+}
+
+/// Devices start with `FRU Device Description : Builtin FRU Device (ID 0)`,
+/// followed by their indented `key : value` fields.
+fn parse_fru_print(output: &str) -> Vec<FruDevice> {
+    let mut devices = Vec::new();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key == "FRU Device Description" {
+            devices.push(new_device(value));
+        } else if let Some(device) = devices.last_mut() {
+            device.fields.insert(key.to_string(), value.to_string());
+        }
+    }
+    for device in &mut devices {
+        let fields = &device.fields;
+        let field = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| fields.get(*key))
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+        let manufacturer = field(&["Product Manufacturer", "Board Mfg"]);
+        let product = field(&["Product Name", "Board Product"]);
+        let serial = field(&["Product Serial", "Board Serial", "Chassis Serial"]);
+        let asset_tag = field(&["Product Asset Tag"]);
+        device.manufacturer = manufacturer;
+        device.product = product;
+        device.serial = serial;
+        device.asset_tag = asset_tag;
+    }
+    devices
+}
+
+fn new_device(description: &str) -> FruDevice {
+    let (description, id) = match description.rsplit_once("(ID ") {
+        Some((name, id)) => (name.trim(), id.trim_end_matches(')').parse().ok()),
+        None => (description, None),
+    };
+    FruDevice {
+        description: description.to_string(),
+        id,
+        manufacturer: None,
+        product: None,
+        serial: None,
+        asset_tag: None,
+        fields: BTreeMap::new(),
+    }
+}
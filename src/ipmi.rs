@@ -0,0 +1,522 @@
+use crate::cassette::Interaction;
+use crate::config::Config;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use utoipa::ToSchema;
+
+/// Set once the BMC turned out to only speak IPMI v1.5, so later commands
+/// skip the failing lanplus attempt.
+static USE_LAN: AtomicBool = AtomicBool::new(false);
+
+pub enum PowerAction {
+    On,
+    Off,
+    /// ACPI graceful shutdown, left to the operating system.
+    Soft,
+    /// Diagnostic interrupt (NMI), e.g. to get a crash dump from a hung kernel.
+    Diag,
+    Status,
+}
+impl PowerAction {
+    /// Actions refused while the kill switch is engaged.
+    pub fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            PowerAction::Off | PowerAction::Soft | PowerAction::Diag
+        )
+    }
+}
+pub enum PowerStatus {
+    On,
+    Off,
+    /// A graceful shutdown was requested, the server may still be on.
+    SoftOff,
+    /// The BMC sent the diagnostic interrupt, the power state is unchanged.
+    Diag,
+    /// A vendor reported a pending change, `is_on` is the state before it.
+    Transitioning {
+        is_on: bool,
+    },
+}
+
+/// State of the chassis identify (locator) LED.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentifyState {
+    Off,
+    /// Blinking until the interval runs out.
+    Temporary,
+    /// On until turned off.
+    Indefinite,
+}
+
+/// Device to boot from next, see `ipmitool chassis bootdev`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BootDevice {
+    Pxe,
+    Disk,
+    /// Enter the BIOS setup.
+    Bios,
+    Cdrom,
+    /// Safe mode, from the default hard drive.
+    Safe,
+}
+
+/// Modifiers of `ipmitool chassis bootdev`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, Default)]
+pub struct BootOptions {
+    /// Keep the override for all later boots instead of the next one only.
+    #[serde(default)]
+    pub persistent: bool,
+    /// Boot in UEFI instead of legacy BIOS mode.
+    #[serde(default)]
+    pub efiboot: bool,
+}
+
+/// Why an ipmitool invocation failed, returned to clients in error bodies.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PowerError {
+    pub kind: PowerErrorKind,
+    /// Repeating the same request later may succeed.
+    pub retryable: bool,
+    pub exit_code: Option<i32>,
+    /// ipmitool's stderr (or unexpected stdout), see `sanitize_output`.
+    pub message: String,
+    /// `message` was cut to `output_limit_bytes`.
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerErrorKind {
+    /// ipmitool could not be started at all.
+    Spawn,
+    /// No session could be established, usually a network or BMC problem.
+    SessionTimeout,
+    AuthFailed,
+    InsufficientPrivilege,
+    KgMismatch,
+    NodeBusy,
+    /// ipmitool succeeded but printed something we do not understand.
+    UnexpectedOutput,
+    /// The BMC accepted the action but the status never reached the target.
+    Unconfirmed,
+    CommandFailed,
+}
+
+impl PowerErrorKind {
+    fn retryable(self) -> bool {
+        matches!(
+            self,
+            PowerErrorKind::SessionTimeout | PowerErrorKind::NodeBusy | PowerErrorKind::Unconfirmed
+        )
+    }
+}
+
+impl PowerError {
+    pub fn new(
+        kind: PowerErrorKind,
+        exit_code: Option<i32>,
+        message: &str,
+        config: &Config,
+    ) -> Self {
+        let (message, truncated) = sanitize_output(message, config);
+        PowerError {
+            kind,
+            retryable: kind.retryable(),
+            exit_code,
+            message,
+            truncated,
+        }
+    }
+}
+
+/// Prepares ipmitool output for returning to clients: removes the BMC
+/// credentials and terminal control characters, and cuts it to
+/// `output_limit_bytes`. Returns whether it was cut.
+pub fn sanitize_output(output: &str, config: &Config) -> (String, bool) {
+    let mut output: String = output
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+    for secret in std::iter::once(&config.password).chain(&config.kg_key) {
+        if !secret.is_empty() {
+            output = output.replace(secret.as_str(), "<redacted>");
+        }
+    }
+    if output.len() <= config.output_limit_bytes {
+        return (output, false);
+    }
+    let mut end = config.output_limit_bytes;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    (output, true)
+}
+
+/// Maps known ipmitool error messages to an error kind.
+fn classify_stderr(stderr: &str) -> PowerErrorKind {
+    let stderr = stderr.to_lowercase();
+    if stderr.contains("insufficient privilege") {
+        PowerErrorKind::InsufficientPrivilege
+    } else if stderr.contains("unauthorized name") || stderr.contains("rakp 2 hmac is invalid") {
+        PowerErrorKind::AuthFailed
+    } else if stderr.contains("rakp 4") || stderr.contains("invalid integrity check") {
+        PowerErrorKind::KgMismatch
+    } else if stderr.contains("node busy") || stderr.contains("insufficient resources for session")
+    {
+        PowerErrorKind::NodeBusy
+    } else if stderr.contains("unable to establish")
+        || stderr.contains("no response")
+        || stderr.contains("timeout")
+    {
+        PowerErrorKind::SessionTimeout
+    } else {
+        PowerErrorKind::CommandFailed
+    }
+}
+
+/// Messages of lanplus failures from BMCs without IPMI v2 support.
+fn lanplus_unsupported(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("get channel cipher suites")
+        || stderr.contains("no matching cipher suite")
+        || (stderr.contains("not supported") && stderr.contains("v2"))
+}
+
+/// Runs ipmitool against the configured BMC and returns its trimmed stdout.
+pub fn run(config: &Config, args: &[&str]) -> Result<String, PowerError> {
+    if config.lan_fallback && USE_LAN.load(Ordering::Relaxed) {
+        return run_with(config, "lan", args);
+    }
+    match run_with(config, "lanplus", args) {
+        Err(e) if config.lan_fallback && lanplus_unsupported(&e.message) => {
+            warn!("BMC does not seem to support lanplus, retrying with IPMI v1.5");
+            let output = run_with(config, "lan", args)?;
+            info!("IPMI v1.5 works, using it from now on");
+            USE_LAN.store(true, Ordering::Relaxed);
+            Ok(output)
+        }
+        result => result,
+    }
+}
+
+/// An ipmitool invocation against the configured BMC, to append the command
+/// to.
+pub fn command(config: &Config, interface: &str) -> std::process::Command {
+    // pass arguments directly, going through a shell breaks on IPv6 literals
+    // and on passwords containing shell metacharacters
+    let mut command = std::process::Command::new("ipmitool");
+    command
+        .args(["-I", interface, "-H", &config.ipmi_address])
+        .args(["-U", &config.username])
+        // -E reads the password from the environment, keeping it out of ps
+        .env("IPMI_PASSWORD", &config.password)
+        .arg("-E");
+    if config.fencing {
+        // fencing agents need a fast answer more than a patient one
+        command.args(["-N", "1", "-R", "2"]);
+    }
+    if let Some(kg_key) = &config.kg_key {
+        // through the environment so the key does not show up in ps
+        command.env("IPMI_KGKEY", kg_key).arg("-K");
+    }
+    command
+}
+
+fn run_with(config: &Config, interface: &str, args: &[&str]) -> Result<String, PowerError> {
+    let replayed = config
+        .cassette
+        .as_ref()
+        .and_then(|cassette| cassette.replayed(interface, args));
+    let output = match replayed {
+        Some(replayed) => {
+            replayed.map_err(|e| PowerError::new(PowerErrorKind::Spawn, None, &e, config))?
+        }
+        None => {
+            let output = command(config, interface)
+                .args(args)
+                .output()
+                .map_err(|e| {
+                    error!("Failed to run ipmitool: {}", e);
+                    PowerError::new(PowerErrorKind::Spawn, None, &e.to_string(), config)
+                })?;
+            let output = Interaction {
+                interface: interface.to_string(),
+                args: args.iter().map(|a| a.to_string()).collect(),
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            };
+            if let Some(cassette) = &config.cassette {
+                cassette.record_interaction(output.clone(), config);
+            }
+            output
+        }
+    };
+    if output.exit_code != Some(0) {
+        error!("Failed to run command: {}", output.stderr);
+        let kind = classify_stderr(&output.stderr);
+        return Err(PowerError::new(
+            kind,
+            output.exit_code,
+            &output.stderr,
+            config,
+        ));
+    }
+    Ok(output.stdout.trim().to_string())
+}
+
+pub fn power_action(action: PowerAction, config: &Config) -> Result<PowerStatus, PowerError> {
+    let action_str = match action {
+        PowerAction::On => "on",
+        PowerAction::Off => "off",
+        PowerAction::Soft => "soft",
+        PowerAction::Diag => "diag",
+        PowerAction::Status => "status",
+    };
+    let output = match (&action, &config.status_command) {
+        (PowerAction::Status, Some(status_command)) => run_custom(status_command, config)?,
+        _ => run(config, &["power", action_str])?,
+    };
+    parse_power_output(&output, config)
+}
+
+/// Runs a `status_command` in place of ipmitool: without a shell, with an
+/// empty environment apart from the password, placeholders filled in.
+fn run_custom(argv: &[String], config: &Config) -> Result<String, PowerError> {
+    let argv: Vec<String> = argv
+        .iter()
+        .map(|arg| {
+            arg.replace("{address}", &config.ipmi_address)
+                .replace("{username}", &config.username)
+        })
+        .collect();
+    let output = std::process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .env_clear()
+        .env("IPMI_PASSWORD", &config.password)
+        .output()
+        .map_err(|e| {
+            error!("Failed to run status command: {}", e);
+            PowerError::new(PowerErrorKind::Spawn, None, &e.to_string(), config)
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        error!("Status command failed: {}", stderr);
+        return Err(PowerError::new(
+            PowerErrorKind::CommandFailed,
+            output.status.code(),
+            &stderr,
+            config,
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn parse_power_output(output: &str, config: &Config) -> Result<PowerStatus, PowerError> {
+    let status = match output {
+        // from a `status_command`
+        "on" => Some(PowerStatus::On),
+        "off" => Some(PowerStatus::Off),
+        "Chassis Power is on" => Some(PowerStatus::On),
+        "Chassis Power is off" => Some(PowerStatus::Off),
+        "Chassis Power Control: Up/On" => Some(PowerStatus::On),
+        "Chassis Power Control: Down/Off" => Some(PowerStatus::Off),
+        "Chassis Power Control: Soft" => Some(PowerStatus::SoftOff),
+        "Chassis Power Control: Diag" => Some(PowerStatus::Diag),
+        // Supermicro and others append the pending transition, e.g.
+        // "Chassis Power is on (soft off pending)"
+        _ if output.starts_with("Chassis Power is on (") => {
+            Some(PowerStatus::Transitioning { is_on: true })
+        }
+        _ if output.starts_with("Chassis Power is off (") => {
+            Some(PowerStatus::Transitioning { is_on: false })
+        }
+        _ => None,
+    };
+    status.ok_or_else(|| {
+        warn!("Unexpected output from ipmitool: {}", output);
+        PowerError::new(PowerErrorKind::UnexpectedOutput, Some(0), output, config)
+    })
+}
+
+/// Interval ipmitool uses for `chassis identify` without an argument.
+pub const DEFAULT_IDENTIFY_SECS: u8 = 15;
+
+/// Turns the identify LED on for `interval` seconds, or off with 0. `None`
+/// keeps it on until turned off.
+pub fn identify(interval: Option<u8>, config: &Config) -> Result<(), PowerError> {
+    let interval = interval.map_or("force".to_string(), |i| i.to_string());
+    run(config, &["chassis", "identify", &interval]).map(|_| ())
+}
+
+/// Reads the identify LED from `chassis status`. Older BMCs do not report it.
+pub fn identify_state(config: &Config) -> Result<Option<IdentifyState>, PowerError> {
+    let output = run(config, &["chassis", "status"])?;
+    let Some(value) = output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Chassis Identify State").then(|| value.trim())
+    }) else {
+        return Ok(None);
+    };
+    match value {
+        "Off" => Ok(Some(IdentifyState::Off)),
+        "Temporary (on)" => Ok(Some(IdentifyState::Temporary)),
+        "Indefinite (on)" => Ok(Some(IdentifyState::Indefinite)),
+        _ => {
+            warn!("Unexpected identify state from ipmitool: {}", value);
+            Err(PowerError::new(
+                PowerErrorKind::UnexpectedOutput,
+                Some(0),
+                value,
+                config,
+            ))
+        }
+    }
+}
+
+/// Sets the device to boot from, on the next boot only unless `persistent`.
+pub fn set_bootdev(
+    device: BootDevice,
+    options: BootOptions,
+    config: &Config,
+) -> Result<(), PowerError> {
+    let device = match device {
+        BootDevice::Pxe => "pxe",
+        BootDevice::Disk => "disk",
+        BootDevice::Bios => "bios",
+        BootDevice::Cdrom => "cdrom",
+        BootDevice::Safe => "safe",
+    };
+    let mut modifiers = Vec::new();
+    if options.persistent {
+        modifiers.push("persistent");
+    }
+    if options.efiboot {
+        modifiers.push("efiboot");
+    }
+    let modifiers = format!("options={}", modifiers.join(","));
+    let mut args = vec!["chassis", "bootdev", device];
+    if options.persistent || options.efiboot {
+        args.push(&modifiers);
+    }
+    run(config, &args).map(|_| ())
+}
+
+/// Sends a raw request to the BMC and returns the response data, without the
+/// completion code (ipmitool fails on anything but success).
+pub fn raw(netfn: u8, cmd: u8, data: &[u8], config: &Config) -> Result<Vec<u8>, PowerError> {
+    let bytes: Vec<String> = [netfn, cmd]
+        .iter()
+        .chain(data)
+        .map(|b| format!("0x{:02x}", b))
+        .collect();
+    let args: Vec<&str> = std::iter::once("raw")
+        .chain(bytes.iter().map(String::as_str))
+        .collect();
+    let output = run(config, &args)?;
+    // hex bytes, e.g. ` 20 01 03 45 02 bf`, wrapped over several lines
+    output
+        .split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| {
+            warn!("Unexpected output from ipmitool raw: {}", output);
+            PowerError::new(PowerErrorKind::UnexpectedOutput, Some(0), &output, config)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cassette::Cassette;
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            "ipmi_address: 10.20.0.5\nusername: admin\npassword: secret\nlisten_port: 8080\ntokens: []\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn power_action_replays_the_cassette() {
+        let path = std::env::temp_dir().join(format!("cassette-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+- interface: lanplus
+  args: [power, status]
+  exit_code: 0
+  stdout: "Chassis Power is on\n"
+  stderr: ""
+- interface: lanplus
+  args: [power, "off"]
+  exit_code: 1
+  stdout: ""
+  stderr: "Unable to establish IPMI v2 / RMCP+ session\n"
+"#,
+        )
+        .unwrap();
+        let mut config = config();
+        config.cassette = Some(Cassette::replay(path.to_str().unwrap()).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            power_action(PowerAction::Status, &config),
+            Ok(PowerStatus::On)
+        ));
+        let Err(error) = power_action(PowerAction::Off, &config) else {
+            panic!("failed run accepted");
+        };
+        assert_eq!(error.exit_code, Some(1));
+        // a run missing from the cassette fails like ipmitool could not start
+        let Err(error) = power_action(PowerAction::On, &config) else {
+            panic!("missing run accepted");
+        };
+        assert_eq!(error.kind, PowerErrorKind::Spawn);
+    }
+
+    #[test]
+    fn parse_power_output_reads_on_and_off() {
+        let config = config();
+        for output in ["Chassis Power is on", "on"] {
+            assert!(matches!(
+                parse_power_output(output, &config),
+                Ok(PowerStatus::On)
+            ));
+        }
+        for output in ["Chassis Power is off", "off"] {
+            assert!(matches!(
+                parse_power_output(output, &config),
+                Ok(PowerStatus::Off)
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_power_output_reads_pending_transitions() {
+        let config = config();
+        assert!(matches!(
+            parse_power_output("Chassis Power is on (soft off pending)", &config),
+            Ok(PowerStatus::Transitioning { is_on: true })
+        ));
+        assert!(matches!(
+            parse_power_output("Chassis Power is off (power on pending)", &config),
+            Ok(PowerStatus::Transitioning { is_on: false })
+        ));
+    }
+
+    #[test]
+    fn parse_power_output_refuses_unexpected_output() {
+        let Err(error) = parse_power_output("Chassis Power is unknown", &config()) else {
+            panic!("unexpected output accepted");
+        };
+        assert_eq!(error.kind, PowerErrorKind::UnexpectedOutput);
+        assert_eq!(error.message, "Chassis Power is unknown");
+    }
+}
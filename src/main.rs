@@ -1,39 +1,284 @@
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{ws::WebSocketUpgrade, Json, Path, Query, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
     Router,
 };
 use axum_auth::AuthBearer;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use log::{error, info, warn};
-use serde::{Deserialize, Serialize};
+use state::{EnrollmentCode, PersistedState};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use timestamp::Timestamp;
+use tower_http::compression::CompressionLayer;
+use tower_http::set_header::SetResponseHeaderLayer;
+use utoipa::OpenApi;
+
+mod api;
+mod bmc;
+mod cassette;
+mod chassis;
+mod config;
+mod console;
+mod dcmi;
+mod diag;
+mod fru;
+mod ipmi;
+mod oem;
+mod openapi;
+mod pef;
+mod rmcp;
+mod sel;
+mod sensor;
+mod state;
+mod timestamp;
+use api::{
+    AccessEntry, AccessReportQuery, BmcPasswordMsg, BmcResetMsg, BmcUserEnabled, BmcUsers, BootDev,
+    BootDevMsg, CanI, CanIQuery, CommandStats, ControlResult, DelegateMsg, DelegateResponse,
+    Disabled, EnrollMsg, EnrollResponse, EnrollmentCodeMsg, EnrollmentCodeResponse, ErrorResponse,
+    ExpiringQuery, Fans, Fru, Identify, IdentifyMsg, KillSwitch, OemOutput, PefFilterEnabled,
+    PowerControlFailure, PowerControlMsg, PowerControlResponse, PowerState, PowerStatusResponse,
+    RawMsg, RawResponse, RestorePolicyMsg, Sel, SelQuery, Sensors, SupportBundle, Thermal,
+    TokenExpiry, WatchdogMsg, MAX_DELEGATION_SECS, MAX_ENROLLMENT_SECS,
+};
+use cassette::Cassette;
+use config::{Action, CanaryConfig, Config, ConfirmConfig, ExpectedState, TokenConfig};
+use dcmi::PowerCapChange;
+use ipmi::{
+    power_action, BootOptions, IdentifyState, PowerAction, PowerError, PowerErrorKind, PowerStatus,
+};
+use sensor::Thresholds;
 
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Args {
     #[arg(short, long)]
     config_file: String,
+    /// Write every ipmitool run to this cassette file
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<String>,
+    /// Answer ipmitool runs from this cassette file instead of the BMC
+    #[arg(long)]
+    replay: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate the config file and report security findings, then exit
+    Check,
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Config,
+    stats: Arc<Mutex<CommandStats>>,
+    /// Target of the last successful on/off request, until a status reading
+    /// confirms it or `transition_window_secs` pass.
+    pending: Arc<Mutex<Option<PendingTransition>>>,
+    persisted: Arc<Mutex<PersistedState>>,
+    /// When each token label last authenticated a request.
+    last_used: Arc<Mutex<BTreeMap<String, Timestamp>>>,
+    /// Caller holding the SOL console, the BMC only allows one session.
+    console: Arc<Mutex<Option<String>>>,
+    /// Why the last write of the state file failed, until one succeeds.
+    /// Meanwhile changes only live in memory.
+    state_error: Arc<Mutex<Option<String>>>,
+    /// Requests being handled, for `load_shedding`.
+    in_flight: Arc<AtomicUsize>,
+}
+/// Why `AppState::authorize` refused a token.
+enum Denied {
+    UnknownToken,
+    /// The action needs an admin (or raw) token.
+    Role(Action),
+    Scope,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Config {
-    ipmi_address: String,
-    username: String,
-    password: String,
-    listen_port: u16,
-    tokens: Vec<String>,
+impl IntoResponse for Denied {
+    fn into_response(self) -> Response {
+        match self {
+            Denied::UnknownToken => (StatusCode::UNAUTHORIZED, "token not in config"),
+            Denied::Role(Action::Raw) => (StatusCode::UNAUTHORIZED, "raw token required"),
+            Denied::Role(_) => (StatusCode::UNAUTHORIZED, "admin token required"),
+            Denied::Scope => (StatusCode::FORBIDDEN, "not in the token's scope"),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PendingTransition {
+    to_on: bool,
+    since: Instant,
 }
-impl Config {
-    fn from_yaml_file(file: &str) -> anyhow::Result<Self> {
-        let file = std::fs::File::open(file)?;
-        let reader = std::io::BufReader::new(file);
-        let config = serde_yaml::from_reader(reader)?;
-        Ok(config)
+impl AppState {
+    /// Looks `token` up in the config and among the enrolled tokens, and
+    /// notes its use.
+    fn find_token(&self, token: &str) -> Option<TokenConfig> {
+        let entry = match self.config.find_token(token) {
+            Some(entry) => entry.clone(),
+            None => {
+                let persisted = self.persisted.lock().unwrap();
+                let entry = persisted.tokens.iter().find(|t| t.token == token)?;
+                if entry.is_expired() {
+                    warn!("Rejecting expired token {}", entry.label());
+                    return None;
+                }
+                entry.clone()
+            }
+        };
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(entry.label(), Timestamp::now());
+        Some(entry)
+    }
+    fn is_admin(&self, token: &str) -> bool {
+        self.find_token(token)
+            .is_some_and(|t| t.allows(Action::Admin))
+    }
+    /// Looks `token` up and checks that it may do `action`.
+    fn authorize(&self, token: &str, action: Action) -> Result<TokenConfig, Denied> {
+        let entry = self.find_token(token).ok_or(Denied::UnknownToken)?;
+        if !entry.has_role_for(action) {
+            return Err(Denied::Role(action));
+        }
+        if !entry.has_in_scope(action) {
+            warn!(
+                "Refusing {:?} for {}, not in its scope",
+                action,
+                entry.label()
+            );
+            return Err(Denied::Scope);
+        }
+        Ok(entry)
+    }
+    /// The config's tokens followed by the enrolled ones.
+    fn tokens(&self) -> Vec<TokenConfig> {
+        let persisted = self.persisted.lock().unwrap();
+        self.config
+            .tokens
+            .iter()
+            .chain(&persisted.tokens)
+            .cloned()
+            .collect()
+    }
+    fn kill_switch_engaged(&self) -> bool {
+        self.config.kill_switch || self.persisted.lock().unwrap().kill_switch
+    }
+    /// Whether the BMC is taken out of service, the config winning over the
+    /// runtime flag.
+    fn disabled(&self) -> Disabled {
+        if self.config.disabled {
+            return Disabled {
+                disabled: true,
+                reason: self.config.disabled_reason.clone(),
+            };
+        }
+        let persisted = self.persisted.lock().unwrap();
+        Disabled {
+            disabled: persisted.disabled,
+            reason: persisted.disabled_reason.clone(),
+        }
+    }
+    /// The 410 to answer requests touching the BMC with while it is disabled.
+    fn out_of_service(&self) -> Option<Response> {
+        let Disabled { disabled, reason } = self.disabled();
+        if !disabled {
+            return None;
+        }
+        let reason = reason.as_deref().unwrap_or("no reason given");
+        info!("Refusing request, BMC is disabled: {}", reason);
+        Some((StatusCode::GONE, format!("disabled: {}", reason)).into_response())
+    }
+    fn set_pending(&self, to_on: bool) {
+        *self.pending.lock().unwrap() = Some(PendingTransition {
+            to_on,
+            since: Instant::now(),
+        });
+    }
+    /// Called with a fresh reading: a pending transition it confirms is
+    /// finished, and its duration refines the convergence estimate.
+    fn settle_pending(&self, is_on: bool) {
+        let Some(pending) = self.pending.lock().unwrap().take() else {
+            return;
+        };
+        if pending.to_on != is_on {
+            return;
+        }
+        let mut persisted = self.persisted.lock().unwrap();
+        persisted.convergence.record(is_on, pending.since.elapsed());
+        if self.config.state_file.is_some() {
+            if let Err(e) = self.save_state(&persisted) {
+                warn!("Failed to save convergence estimate: {}", e);
+            }
+        }
+    }
+    /// Writes `persisted` to the state file, noting whether that worked.
+    fn save_state(&self, persisted: &PersistedState) -> anyhow::Result<()> {
+        let result = persisted.save(self.config.state_file.as_deref());
+        let mut state_error = self.state_error.lock().unwrap();
+        match &result {
+            Ok(()) if state_error.take().is_some() => info!("State file written again"),
+            Ok(()) => {}
+            Err(e) => *state_error = Some(e.to_string()),
+        }
+        result
+    }
+    fn state_error(&self) -> Option<String> {
+        self.state_error.lock().unwrap().clone()
+    }
+    /// Returns the transition still within the window, if any.
+    fn pending(&self) -> Option<PendingTransition> {
+        let window = Duration::from_secs(self.config.transition_window_secs);
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_some_and(|p| p.since.elapsed() > window) {
+            *pending = None;
+        }
+        *pending
+    }
+    /// Seconds until a transition started `elapsed` ago usually completes.
+    fn eta_seconds(&self, to_on: bool, elapsed: Duration) -> Option<u64> {
+        let estimate = self.persisted.lock().unwrap().convergence.estimate(to_on)?;
+        Some(estimate.saturating_sub(elapsed).as_secs())
+    }
+    /// Runs an ipmitool command and accounts it to `caller` in the stats.
+    /// ipmitool blocks until the BMC answers, so it runs on the blocking pool
+    /// and a slow BMC cannot starve the other requests of runtime threads.
+    async fn ipmi<T: Send + 'static>(
+        &self,
+        caller: &str,
+        command: impl FnOnce(&Config) -> Result<T, PowerError> + Send + 'static,
+    ) -> Result<T, PowerError> {
+        let state = self.clone();
+        let caller = caller.to_string();
+        tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let result = command(&state.config);
+            state
+                .stats
+                .lock()
+                .unwrap()
+                .record(&caller, started.elapsed(), result.as_ref().err());
+            result
+        })
+        .await
+        .expect("ipmitool command panicked")
     }
-    fn validate_token(&self, token: &str) -> bool {
-        self.tokens.contains(&token.to_string())
+    async fn power_action(
+        &self,
+        action: PowerAction,
+        caller: &str,
+    ) -> Result<PowerStatus, PowerError> {
+        self.ipmi(caller, move |config| power_action(action, config))
+            .await
     }
 }
 
@@ -42,12 +287,130 @@ async fn main() {
     // setup logger
     env_logger::init();
     let args = Args::parse();
-    let config = Config::from_yaml_file(&args.config_file).expect("Failed to read config file");
+    let mut config = Config::from_yaml_file(&args.config_file).expect("Failed to read config file");
+    let findings = config.lint();
+    if let Some(Command::Check) = args.command {
+        for finding in &findings {
+            println!("{}", finding);
+        }
+        if !findings.is_empty() {
+            std::process::exit(1);
+        }
+        println!("Config OK");
+        return;
+    }
+    for finding in &findings {
+        warn!("{}", finding);
+    }
+    if let Some(path) = &args.record {
+        config.cassette = Some(Cassette::record(path));
+    }
+    if let Some(path) = &args.replay {
+        config.cassette = Some(Cassette::replay(path).expect("Failed to read cassette"));
+    }
+    if config.strict_security && !findings.is_empty() {
+        error!("Refusing to start with security findings, strict_security is set");
+        std::process::exit(1);
+    }
+    let state = AppState {
+        config: config.clone(),
+        stats: Arc::new(Mutex::new(CommandStats::default())),
+        pending: Arc::new(Mutex::new(None)),
+        persisted: Arc::new(Mutex::new(
+            PersistedState::load(config.state_file.as_deref()).expect("Failed to read state file"),
+        )),
+        last_used: Arc::new(Mutex::new(BTreeMap::new())),
+        console: Arc::new(Mutex::new(None)),
+        state_error: Arc::new(Mutex::new(None)),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+    };
+    for token in &state.tokens() {
+        if token.is_expired() {
+            warn!("Token {} has expired", token.label());
+        } else if token.expires_within(std::time::Duration::from_secs(7 * 24 * 3600)) {
+            warn!("Token {} expires within a week", token.label());
+        }
+    }
+    if state.kill_switch_engaged() {
+        warn!("Kill switch is engaged, destructive actions are refused");
+    }
+    if state.disabled().disabled {
+        warn!("BMC is disabled, requests touching it are refused");
+    }
+    if let Some(canary) = config.canary.clone() {
+        tokio::spawn(run_canary(canary, state.clone()));
+    }
+    if config.state_file.is_some() {
+        tokio::spawn(flush_state(state.clone()));
+    }
     let app = Router::new()
         .route("/power", get(get_power_status))
         .route("/power", post(power_control))
-        .with_state(config.clone())
+        .route("/power/usage", get(get_power_usage))
+        .route("/power/cap", get(get_power_cap).put(set_power_cap))
+        .route("/chassis/status", get(get_chassis_status))
+        .route("/chassis/poh", get(get_power_on_hours))
+        .route("/chassis/policy", put(set_restore_policy))
+        .route("/identify", get(get_identify).post(identify))
+        .route("/bootdev", post(set_bootdev))
+        .route("/sensors", get(get_sensors))
+        .route("/sensors/:name", get(get_sensor))
+        .route(
+            "/sensors/:name/thresholds",
+            get(get_sensor_thresholds).put(set_sensor_thresholds),
+        )
+        .route("/thermal", get(get_thermal))
+        .route("/fans", get(get_fans))
+        .route("/sel", get(get_sel).delete(clear_sel))
+        .route("/sel/info", get(get_sel_info))
+        .route("/fru", get(get_fru))
+        .route("/console", get(console))
+        .route("/bmc/info", get(get_bmc_info))
+        .route("/bmc/reset", post(reset_bmc))
+        .route("/bmc/lan", get(get_bmc_lan))
+        .route("/bmc/users", get(bmc_users))
+        .route("/bmc/users/:id/password", put(set_bmc_password))
+        .route("/bmc/users/:id/enabled", put(set_bmc_user_enabled))
+        .route("/bmc/pef", get(get_pef))
+        .route("/bmc/pef/filters/:id/enabled", put(set_pef_filter_enabled))
+        .route("/watchdog", get(get_watchdog).post(watchdog))
+        .route("/diag", get(get_diag))
+        .route("/raw", post(raw))
+        .route("/oem/:command", post(oem_command))
+        .route("/readyz", get(readyz))
+        .route("/openapi.json", get(openapi_json))
+        .route("/metrics/me", get(my_metrics))
+        .route("/auth/can-i", get(can_i))
+        .route("/auth/enroll", post(enroll))
+        .route("/auth/delegate", post(delegate))
+        .route("/admin/enrollment-codes", post(mint_enrollment_code))
+        .route("/admin/tokens/expiring", get(expiring_tokens))
+        .route("/admin/access-report", get(access_report))
+        .route("/admin/stats", get(command_stats))
+        .route("/admin/support-bundle", get(support_bundle))
+        .route(
+            "/admin/kill-switch",
+            get(get_kill_switch).put(set_kill_switch),
+        )
+        .route("/admin/disabled", get(get_disabled).put(set_disabled))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            degraded_header,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), shed_load))
+        .with_state(state)
         .fallback(default_404);
+    let app = if config.compression {
+        app.layer(CompressionLayer::new())
+    } else {
+        app
+    };
+    let headers = config
+        .response_headers()
+        .expect("Headers were checked when loading the config");
+    let app = headers.into_iter().fold(app, |app, (name, value)| {
+        app.layer(SetResponseHeaderLayer::overriding(name, value))
+    });
     let addr = format!("0.0.0.0:{}", config.listen_port);
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -58,92 +421,2267 @@ async fn main() {
     info!("Server started on port {}", config.listen_port);
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct PowerControlMsg {
-    action: String,
-}
-enum PowerAction {
-    On,
-    Off,
-    Status,
+/// Flags responses while the state file cannot be written.
+async fn degraded_header(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if state.state_error().is_some() {
+        response
+            .headers_mut()
+            .insert("x-degraded", HeaderValue::from_static("state_file"));
+    }
+    response
 }
-enum PowerStatus {
-    On,
-    Off,
+
+/// Decrements the in-flight count when a request is done, however it ends.
+struct InFlight(Arc<AtomicUsize>);
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
-fn power_action(action: PowerAction, config: &Config) -> Option<PowerStatus> {
-    let action_str = match action {
-        PowerAction::On => "on".to_string(),
-        PowerAction::Off => "off".to_string(),
-        PowerAction::Status => "status".to_string(),
+
+/// Refuses status polls and listings while too many requests are in flight,
+/// see `LoadShedding`.
+async fn shed_load(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let in_flight = state.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+    let _in_flight = InFlight(state.in_flight.clone());
+    let Some(shedding) = &state.config.load_shedding else {
+        return next.run(request).await;
     };
-    let command = format!(
-        "ipmitool -I lanplus -H {} -U {} -P {} power {}",
-        config.ipmi_address, config.username, config.password, action_str
-    );
-    let output = std::process::Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .expect("Failed to run command");
-    if !output.status.success() {
-        error!(
-            "Failed to run command: {}",
-            String::from_utf8_lossy(&output.stderr)
+    let path = request.uri().path();
+    let low_priority =
+        request.method() == Method::GET && path != "/readyz" && !path.starts_with("/admin/");
+    if low_priority && in_flight > shedding.max_in_flight {
+        info!(
+            "Shedding request for {} with {} requests in flight",
+            path, in_flight
         );
-        return None;
+        state.stats.lock().unwrap().shed_requests += 1;
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, shedding.retry_after_secs.to_string())],
+            "overloaded, retry later",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Retries writing the state file while it fails, so changes kept in memory
+/// meanwhile are not lost on restart.
+async fn flush_state(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        if state.state_error().is_none() {
+            continue;
+        }
+        let persisted = state.persisted.lock().unwrap();
+        if let Err(e) = state.save_state(&persisted) {
+            warn!("State file still cannot be written: {}", e);
+        }
     }
-    let command_out = output.stdout;
-    let output = String::from_utf8_lossy(&command_out);
-    let output = output.trim();
-    match output {
-        "Chassis Power is on" => Some(PowerStatus::On),
-        "Chassis Power is off" => Some(PowerStatus::Off),
-        "Chassis Power Control: Up/On" => Some(PowerStatus::On),
-        "Chassis Power Control: Soft" => Some(PowerStatus::Off),
-        _ => {
-            warn!("Unexpected output from ipmitool: {}", output);
-            None
+}
+
+async fn run_canary(canary: CanaryConfig, state: AppState) {
+    info!(
+        "Starting canary probe every {}s, alerting after {} consecutive failures",
+        canary.interval_secs, canary.failure_threshold
+    );
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(canary.interval_secs));
+    let mut failures = 0;
+    loop {
+        interval.tick().await;
+        if state.disabled().disabled {
+            continue;
+        }
+        let status = state.power_action(PowerAction::Status, "canary").await;
+        if status.is_ok() {
+            if failures >= canary.failure_threshold {
+                info!("Canary probe recovered after {} failures", failures);
+            }
+            failures = 0;
+            continue;
+        }
+        failures += 1;
+        if failures == canary.failure_threshold {
+            error!(
+                "Canary probe failed {} times in a row, BMC at {} may be unreachable",
+                failures, state.config.ipmi_address
+            );
+        } else {
+            warn!("Canary probe failed ({} in a row)", failures);
         }
     }
 }
 
-async fn get_power_status(State(config): State<Config>) -> impl IntoResponse {
+#[utoipa::path(
+    get,
+    path = "/power",
+    security(()),
+    responses(
+        (status = 200, body = PowerStatusResponse),
+        (status = 500, description = "status unknown", body = PowerStatusResponse),
+    )
+)]
+async fn get_power_status(State(state): State<AppState>) -> impl IntoResponse {
     info!("Got request for power status");
-    let resp = match power_action(PowerAction::Status, &config) {
-        Some(PowerStatus::On) => (StatusCode::OK, "{\"is_on\": true}"),
-        Some(PowerStatus::Off) => (StatusCode::OK, "{\"is_on\": false}"),
-        None => (StatusCode::INTERNAL_SERVER_ERROR, "error"),
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    let config = &state.config;
+    let reading = state.power_action(PowerAction::Status, "anonymous").await;
+    let error = reading.as_ref().err().cloned();
+    let pending = state.pending();
+    let (is_on, status) = match (reading.ok(), pending.map(|p| p.to_on)) {
+        // vendors reporting a pending change tell us which way it goes
+        (Some(PowerStatus::Transitioning { is_on: true }), _) => {
+            (Some(true), PowerState::PoweringOff)
+        }
+        (Some(PowerStatus::Transitioning { is_on: false }), _) => {
+            (Some(false), PowerState::PoweringOn)
+        }
+        (Some(PowerStatus::On), Some(false)) => (Some(true), PowerState::PoweringOff),
+        (Some(PowerStatus::Off), Some(true)) => (Some(false), PowerState::PoweringOn),
+        (Some(PowerStatus::On), _) => {
+            state.settle_pending(true);
+            (Some(true), PowerState::On)
+        }
+        (Some(PowerStatus::Off | PowerStatus::SoftOff), _) => {
+            state.settle_pending(false);
+            (Some(false), PowerState::Off)
+        }
+        // BMCs often stop answering for a while right after an action
+        (None, Some(true)) => (Some(false), PowerState::PoweringOn),
+        (None, Some(false)) => (Some(true), PowerState::PoweringOff),
+        // only answered to `power diag`, never to a status query
+        (Some(PowerStatus::Diag), _) | (None, None) => (None, PowerState::Unknown),
+    };
+    let drifted = matches!(
+        (status, config.expected_state),
+        (PowerState::On, ExpectedState::Off) | (PowerState::Off, ExpectedState::On)
+    );
+    if drifted {
+        warn!(
+            "Power state drifted: expected {:?}, status is {:?}",
+            config.expected_state, status
+        );
+    }
+    let eta_seconds = match (status, pending) {
+        (PowerState::PoweringOn | PowerState::PoweringOff, Some(p)) => {
+            state.eta_seconds(p.to_on, p.since.elapsed())
+        }
+        _ => None,
+    };
+    let resp = PowerStatusResponse {
+        is_on,
+        status,
+        drifted,
+        eta_seconds,
+        error,
     };
-    info!("Returning status: {}", resp.1);
-    resp
+    info!("Returning status: {:?}", resp);
+    let code = match resp.status {
+        PowerState::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::OK,
+    };
+    (code, Json(resp)).into_response()
 }
 
+#[utoipa::path(
+    post,
+    path = "/power",
+    request_body = PowerControlMsg,
+    responses(
+        (status = 200, body = PowerControlResponse),
+        (status = 500, description = "an action failed", body = PowerControlFailure),
+    )
+)]
 async fn power_control(
-    State(config): State<Config>,
+    State(state): State<AppState>,
     AuthBearer(token): AuthBearer,
     Json(payload): Json<PowerControlMsg>,
 ) -> impl IntoResponse {
     info!("Got request to power on");
-    info!("Token: {}", token);
-    if !config.validate_token(&token) {
-        return (StatusCode::UNAUTHORIZED, "token not in config");
+    let config = &state.config;
+    let Some(entry) = state.find_token(&token) else {
+        return Denied::UnknownToken.into_response();
+    };
+    let caller = entry.label();
+    info!("Caller: {}", caller);
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    if config.strict_requests {
+        if let Some(field) = payload.unknown.keys().next() {
+            warn!("Rejecting request with unknown field: {}", field);
+            return (StatusCode::BAD_REQUEST, format!("unknown field: {}", field)).into_response();
+        }
+    }
+    let requested: Vec<&String> = payload.action.iter().chain(&payload.actions).collect();
+    if requested.is_empty() {
+        warn!("Request carries no action");
+        return (StatusCode::BAD_REQUEST, "error").into_response();
+    }
+    let mut actions = Vec::new();
+    for action in requested {
+        let (action, allowed) = match action.as_str() {
+            "on" => (PowerAction::On, Action::On),
+            "off" => (PowerAction::Off, Action::Off),
+            "soft" => (PowerAction::Soft, Action::Soft),
+            "diag" => (PowerAction::Diag, Action::Diag),
+            _ => {
+                warn!("Invalid action: {}", action);
+                return (StatusCode::BAD_REQUEST, "error").into_response();
+            }
+        };
+        if !entry.allows(allowed) {
+            warn!("Refusing {:?} for {}, not in its scope", allowed, caller);
+            return Denied::Scope.into_response();
+        }
+        actions.push(action);
+    }
+    if actions.iter().any(|a| a.is_destructive()) && state.kill_switch_engaged() {
+        warn!("Refusing destructive action, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    let reason = payload
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    if actions.iter().any(|a| a.is_destructive()) {
+        match reason {
+            Some(reason) => info!("{} gave the reason: {}", caller, reason),
+            None if config.require_reason => {
+                warn!("Refusing destructive action by {} without a reason", caller);
+                return (StatusCode::BAD_REQUEST, "reason required").into_response();
+            }
+            None => {}
+        }
+    }
+    let mut results = Vec::new();
+    for action in actions {
+        let target = match action {
+            PowerAction::On => Some(true),
+            PowerAction::Off | PowerAction::Soft => Some(false),
+            PowerAction::Diag | PowerAction::Status => None,
+        };
+        // a graceful shutdown takes as long as the OS needs, don't wait for it
+        let confirmable = !matches!(action, PowerAction::Soft);
+        match state.power_action(action, &caller).await {
+            Ok(PowerStatus::On) => {
+                info!("Power is on");
+                results.push(ControlResult::On);
+            }
+            Ok(PowerStatus::Off) => {
+                info!("Power is off");
+                results.push(ControlResult::Off);
+            }
+            Ok(PowerStatus::SoftOff) => {
+                info!("Soft power off requested");
+                results.push(ControlResult::SoftOff);
+            }
+            Ok(PowerStatus::Diag) => {
+                info!("Diagnostic interrupt sent");
+                results.push(ControlResult::Diag);
+            }
+            Ok(PowerStatus::Transitioning { .. }) => {
+                info!("Power is transitioning");
+                results.push(ControlResult::Pending);
+            }
+            Err(error) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(PowerControlFailure { results, error }),
+                )
+                    .into_response()
+            }
+        }
+        if let Some(to_on) = target {
+            state.set_pending(to_on);
+            let confirm = match &config.confirm_by_status {
+                _ if !confirmable => None,
+                Some(confirm) => Some(confirm.clone()),
+                None if config.fencing && !to_on => Some(ConfirmConfig::default()),
+                None => None,
+            };
+            if let Some(confirm) = confirm {
+                if let Err(error) = confirm_state(&state, to_on, &caller, &confirm).await {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(PowerControlFailure { results, error }),
+                    )
+                        .into_response();
+                }
+            }
+        }
+    }
+    (StatusCode::OK, Json(PowerControlResponse { results })).into_response()
+}
+
+/// Polls the power status until it reads `to_on`, for BMCs acknowledging
+/// actions they then fail to carry out.
+async fn confirm_state(
+    state: &AppState,
+    to_on: bool,
+    caller: &str,
+    confirm: &ConfirmConfig,
+) -> Result<(), PowerError> {
+    let timeout = Duration::from_secs(confirm.timeout_secs);
+    let started = Instant::now();
+    loop {
+        match state.power_action(PowerAction::Status, caller).await {
+            Ok(PowerStatus::On) if to_on => break,
+            Ok(PowerStatus::Off | PowerStatus::SoftOff) if !to_on => break,
+            _ if started.elapsed() >= timeout => {
+                warn!("Power did not reach the requested state in {:?}", timeout);
+                return Err(PowerError::new(
+                    PowerErrorKind::Unconfirmed,
+                    None,
+                    &format!(
+                        "power not {} after {}s",
+                        if to_on { "on" } else { "off" },
+                        confirm.timeout_secs
+                    ),
+                    &state.config,
+                ));
+            }
+            _ => tokio::time::sleep(Duration::from_secs(confirm.interval_secs)).await,
+        }
+    }
+    state.settle_pending(to_on);
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/power/usage",
+    responses(
+        (status = 200, body = PowerReading),
+        (status = 501, description = "not reported by this BMC"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_power_usage(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, dcmi::power_reading).await {
+        Ok(Some(reading)) => (StatusCode::OK, Json(reading)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_IMPLEMENTED,
+            "power reading not reported by this BMC",
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/power/cap",
+    responses(
+        (status = 200, body = PowerCap),
+        (status = 501, description = "not reported by this BMC"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_power_cap(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    power_cap_response(&state, &caller).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/power/cap",
+    request_body = PowerCapChange,
+    responses(
+        (status = 200, body = PowerCap),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_power_cap(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<PowerCapChange>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::PowerCap) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    // the exception action of a cap can be a hard power off
+    if state.kill_switch_engaged() {
+        warn!("Refusing power cap change, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    info!("Power cap change {:?} requested by {}", payload, caller);
+    if let Err(error) = state
+        .ipmi(&caller, move |config| dcmi::set_power_cap(&payload, config))
+        .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response();
+    }
+    power_cap_response(&state, &caller).await
+}
+
+async fn power_cap_response(state: &AppState, caller: &str) -> Response {
+    match state.ipmi(caller, dcmi::power_cap).await {
+        Ok(Some(cap)) => (StatusCode::OK, Json(cap)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_IMPLEMENTED,
+            "power limit not reported by this BMC",
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/chassis/status",
+    responses(
+        (status = 200, body = ChassisStatus),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_chassis_status(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for chassis status");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, chassis::status).await {
+        Ok(Some(status)) => (StatusCode::OK, Json(status)).into_response(),
+        Ok(None) => {
+            warn!("Unexpected output from ipmitool chassis status");
+            (
+                StatusCode::NOT_IMPLEMENTED,
+                "chassis status not understood for this BMC",
+            )
+                .into_response()
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/chassis/poh",
+    responses(
+        (status = 200, body = PowerOnHours),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_power_on_hours(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for power-on hours");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, chassis::power_on_hours).await {
+        Ok(Some(poh)) => (StatusCode::OK, Json(poh)).into_response(),
+        Ok(None) => {
+            warn!("Unexpected output from ipmitool chassis poh");
+            (
+                StatusCode::NOT_IMPLEMENTED,
+                "power-on hours not understood for this BMC",
+            )
+                .into_response()
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/chassis/policy",
+    request_body = RestorePolicyMsg,
+    responses(
+        (status = 200, body = RestorePolicyMsg),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_restore_policy(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<RestorePolicyMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::RestorePolicy) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    if state.kill_switch_engaged() {
+        warn!("Refusing restore policy change, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    info!(
+        "Power restore policy {:?} requested by {}",
+        payload.policy, caller
+    );
+    match state
+        .ipmi(&caller, move |config| {
+            chassis::set_restore_policy(payload.policy, config)
+        })
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/identify",
+    responses(
+        (status = 200, body = Identify),
+        (status = 501, description = "not reported by this BMC"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_identify(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for identify state");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, ipmi::identify_state).await {
+        Ok(Some(state)) => (StatusCode::OK, Json(Identify { state })).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_IMPLEMENTED,
+            "identify state not reported by this BMC",
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/identify",
+    request_body = IdentifyMsg,
+    responses(
+        (status = 200, body = Identify),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn identify(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<IdentifyMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Identify) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    let (interval, identify_state) = match (payload.force, payload.interval) {
+        (true, Some(_)) => {
+            return (StatusCode::BAD_REQUEST, "force and interval are exclusive").into_response()
+        }
+        (true, None) => (None, IdentifyState::Indefinite),
+        (false, Some(0)) => (Some(0), IdentifyState::Off),
+        (false, interval) => (
+            Some(interval.unwrap_or(ipmi::DEFAULT_IDENTIFY_SECS)),
+            IdentifyState::Temporary,
+        ),
+    };
+    info!("Identify {:?} requested by {}", identify_state, caller);
+    match state
+        .ipmi(&caller, move |config| ipmi::identify(interval, config))
+        .await
+    {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(Identify {
+                state: identify_state,
+            }),
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/bootdev",
+    request_body = BootDevMsg,
+    responses(
+        (status = 200, body = BootDev),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_bootdev(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<BootDevMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Bootdev) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    let defaults = state.config.bootdev;
+    let options = BootOptions {
+        persistent: payload.persistent.unwrap_or(defaults.persistent),
+        efiboot: payload.efiboot.unwrap_or(defaults.efiboot),
+    };
+    info!(
+        "Boot device {:?} ({:?}) requested by {}",
+        payload.device, options, caller
+    );
+    let device = payload.device;
+    match state
+        .ipmi(&caller, move |config| {
+            ipmi::set_bootdev(device, options, config)
+        })
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(BootDev { device, options })).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/sensors",
+    responses(
+        (status = 200, body = Sensors),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_sensors(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for sensor readings");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, sensor::sensors).await {
+        Ok(sensors) => (StatusCode::OK, Json(Sensors { sensors })).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/thermal",
+    responses(
+        (status = 200, body = Thermal),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_thermal(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for temperatures");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, sensor::sensors).await {
+        Ok(sensors) => (StatusCode::OK, Json(Thermal::from_sensors(&sensors))).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/fans",
+    responses(
+        (status = 200, body = Fans),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_fans(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for fan speeds");
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, sensor::sensors).await {
+        Ok(sensors) => (StatusCode::OK, Json(Fans::from_sensors(&sensors))).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/sensors/{name}",
+    params(("name" = String, Path, description = "Sensor name as printed by ipmitool")),
+    responses(
+        (status = 200, body = Sensor),
+        (status = 404, description = "no such sensor"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_sensor(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    info!("Got request for sensor {}", name);
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state
+        .ipmi(&caller, move |config| sensor::sensor(&name, config))
+        .await
+    {
+        Ok(Some(sensor)) => (StatusCode::OK, Json(sensor)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "no such sensor").into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/sensors/{name}/thresholds",
+    params(("name" = String, Path, description = "Sensor name as printed by ipmitool")),
+    responses(
+        (status = 200, body = Thresholds),
+        (status = 404, description = "no such sensor"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_sensor_thresholds(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    info!("Got request for thresholds of sensor {}", name);
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state
+        .ipmi(&caller, move |config| sensor::sensor(&name, config))
+        .await
+    {
+        Ok(Some(sensor)) => (StatusCode::OK, Json(sensor.thresholds)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "no such sensor").into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/sensors/{name}/thresholds",
+    params(("name" = String, Path, description = "Sensor name as printed by ipmitool")),
+    request_body = Thresholds,
+    responses(
+        (status = 200, body = Thresholds),
+        (status = 404, description = "no such sensor"),
+        (status = 422, description = "thresholds out of order"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_sensor_thresholds(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(name): Path<String>,
+    Json(payload): Json<Thresholds>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::SensorThresholds) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    let caller = caller.label();
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    // the order is checked against the thresholds left as they are, too
+    let sensor_name = name.clone();
+    let current = match state
+        .ipmi(&caller, move |config| sensor::sensor(&sensor_name, config))
+        .await
+    {
+        Ok(Some(sensor)) => sensor.thresholds,
+        Ok(None) => return (StatusCode::NOT_FOUND, "no such sensor").into_response(),
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error }),
+            )
+                .into_response()
+        }
+    };
+    let updated = current.merged(&payload);
+    if let Err(e) = updated.check_order() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    warn!(
+        "Thresholds of sensor {} changed by {}: {:?}",
+        name, caller, payload
+    );
+    match state
+        .ipmi(&caller, move |config| {
+            sensor::set_thresholds(&name, &payload, config)
+        })
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(updated)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/sel",
+    params(SelQuery),
+    responses(
+        (status = 200, body = Sel),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_sel(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<SelQuery>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    info!("Got request for the SEL from {}", caller);
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    let mut entries = match state.ipmi(&caller, sel::sel).await {
+        Ok(entries) => entries,
+        Err(error) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error }),
+            )
+                .into_response()
+        }
+    };
+    if let Some(since) = query.since {
+        entries.retain(|e| e.timestamp.is_some_and(|t| t >= since));
+    }
+    if let Some(limit) = query.limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    (StatusCode::OK, Json(Sel { entries })).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/sel/info",
+    responses(
+        (status = 200, body = SelInfo),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_sel_info(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, sel::info).await {
+        Ok(Some(info)) => (StatusCode::OK, Json(info)).into_response(),
+        Ok(None) => {
+            warn!("Unexpected output from ipmitool sel info");
+            (
+                StatusCode::NOT_IMPLEMENTED,
+                "SEL info not reported by this BMC",
+            )
+                .into_response()
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/sel",
+    responses(
+        (status = 204, description = "cleared"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn clear_sel(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::SelClear) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    if state.kill_switch_engaged() {
+        warn!("Refusing to clear the SEL, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    warn!("SEL cleared by {}", caller);
+    match state.ipmi(&caller, sel::clear).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/fru",
+    responses(
+        (status = 200, body = Fru),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_fru(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    info!("Got request for the FRU inventory from {}", caller);
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, fru::inventory).await {
+        Ok(devices) => (StatusCode::OK, Json(Fru { devices })).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/console",
+    responses(
+        (status = 101, description = "WebSocket carrying the serial-over-LAN console"),
+        (status = 409, description = "another console is open"),
+    )
+)]
+async fn console(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    upgrade: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Console) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    {
+        let mut session = state.console.lock().unwrap();
+        if let Some(holder) = &*session {
+            return (
+                StatusCode::CONFLICT,
+                format!("console in use by {}", holder),
+            )
+                .into_response();
+        }
+        *session = Some(caller.clone());
+    }
+    info!("Console opened by {}", caller);
+    let failed_state = state.clone();
+    upgrade
+        .on_failed_upgrade(move |e| {
+            warn!("Console upgrade failed: {}", e);
+            *failed_state.console.lock().unwrap() = None;
+        })
+        .on_upgrade(move |socket| async move {
+            let idle_timeout = Duration::from_secs(state.config.console_idle_timeout_secs);
+            console::bridge(socket, state.config.clone(), idle_timeout).await;
+            *state.console.lock().unwrap() = None;
+            info!("Console closed by {}", caller);
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/bmc/info",
+    responses(
+        (status = 200, body = BmcInfo),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_bmc_info(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, bmc::info).await {
+        Ok(info) => (StatusCode::OK, Json(info)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/bmc/reset",
+    request_body = BmcResetMsg,
+    responses(
+        (status = 200, body = BmcResetMsg),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn reset_bmc(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<BmcResetMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::BmcReset) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    // a cold reset drops every session and the serial console
+    if state.kill_switch_engaged() {
+        warn!("Refusing BMC reset, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    let reason = payload
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    match reason {
+        Some(reason) => info!("{} gave the reason: {}", caller, reason),
+        None if state.config.require_reason => {
+            warn!("Refusing BMC reset by {} without a reason", caller);
+            return (StatusCode::BAD_REQUEST, "reason required").into_response();
+        }
+        None => {}
+    }
+    warn!("BMC {:?} reset requested by {}", payload.mode, caller);
+    match state
+        .ipmi(&caller, move |config| bmc::reset(payload.mode, config))
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/bmc/lan",
+    responses(
+        (status = 200, body = BmcLan),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_bmc_lan(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, bmc::lan).await {
+        Ok(lan) => (StatusCode::OK, Json(lan)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/bmc/users",
+    responses(
+        (status = 200, body = BmcUsers),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn bmc_users(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::BmcUsers) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
     };
-    let action = match payload.action.as_str() {
-        "on" => PowerAction::On,
-        "off" => PowerAction::Off,
-        _ => {
-            warn!("Invalid action: {}", payload.action);
-            return (StatusCode::BAD_REQUEST, "error");
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller.label(), bmc::users).await {
+        Ok(users) => (StatusCode::OK, Json(BmcUsers { users })).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+/// Why `check_not_own_user` refused a change.
+enum UserCheckError {
+    Ipmi(PowerError),
+    OwnUser,
+}
+
+impl IntoResponse for UserCheckError {
+    fn into_response(self) -> Response {
+        match self {
+            UserCheckError::Ipmi(error) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error }),
+            )
+                .into_response(),
+            UserCheckError::OwnUser => (
+                StatusCode::CONFLICT,
+                "refusing to change the BMC user this service logs in as",
+            )
+                .into_response(),
         }
+    }
+}
+
+/// Refuses changes to the BMC user this service logs in as, which would lock
+/// it out since its password comes from the config.
+async fn check_not_own_user(state: &AppState, caller: &str, id: u8) -> Result<(), UserCheckError> {
+    let users = state
+        .ipmi(caller, bmc::users)
+        .await
+        .map_err(UserCheckError::Ipmi)?;
+    if users
+        .iter()
+        .any(|u| u.id == id && u.name == state.config.username)
+    {
+        return Err(UserCheckError::OwnUser);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/bmc/users/{id}/password",
+    params(("id" = u8, Path, description = "BMC user ID")),
+    request_body = BmcPasswordMsg,
+    responses(
+        (status = 204, description = "password changed"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_bmc_password(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(id): Path<u8>,
+    Json(payload): Json<BmcPasswordMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::BmcUsers) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
     };
-    match power_action(action, &config) {
-        Some(PowerStatus::On) => info!("Power is on"),
-        Some(PowerStatus::Off) => info!("Power is off"),
-        None => return (StatusCode::INTERNAL_SERVER_ERROR, "error"),
+    let caller = caller.label();
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    if payload.password.is_empty() || payload.password.len() > bmc::MAX_PASSWORD_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("password must be 1 to {} bytes long", bmc::MAX_PASSWORD_LEN),
+        )
+            .into_response();
+    }
+    if let Err(refused) = check_not_own_user(&state, &caller, id).await {
+        return refused.into_response();
+    }
+    warn!("Password of BMC user {} changed by {}", id, caller);
+    match state
+        .ipmi(&caller, move |config| {
+            bmc::set_user_password(id, &payload.password, config)
+        })
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/bmc/users/{id}/enabled",
+    params(("id" = u8, Path, description = "BMC user ID")),
+    request_body = BmcUserEnabled,
+    responses(
+        (status = 200, body = BmcUserEnabled),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_bmc_user_enabled(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(id): Path<u8>,
+    Json(payload): Json<BmcUserEnabled>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::BmcUsers) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    let caller = caller.label();
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    if let Err(refused) = check_not_own_user(&state, &caller, id).await {
+        return refused.into_response();
+    }
+    warn!(
+        "BMC user {} {} by {}",
+        id,
+        if payload.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        caller
+    );
+    match state
+        .ipmi(&caller, move |config| {
+            bmc::set_user_enabled(id, payload.enabled, config)
+        })
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/bmc/pef",
+    responses(
+        (status = 200, body = Pef),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_pef(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    match state.ipmi(&caller, pef::pef).await {
+        Ok(pef) => (StatusCode::OK, Json(pef)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/bmc/pef/filters/{id}/enabled",
+    params(("id" = u8, Path, description = "Event filter table entry")),
+    request_body = PefFilterEnabled,
+    responses(
+        (status = 200, body = PefFilterEnabled),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn set_pef_filter_enabled(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(id): Path<u8>,
+    Json(payload): Json<PefFilterEnabled>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Pef) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    let caller = caller.label();
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    warn!(
+        "PEF filter {} {} by {}",
+        id,
+        if payload.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        caller
+    );
+    match state
+        .ipmi(&caller, move |config| {
+            pef::set_filter_enabled(id, payload.enabled, config)
+        })
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/watchdog",
+    responses(
+        (status = 200, body = Watchdog),
+        (status = 501, description = "not reported by this BMC"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn get_watchdog(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    watchdog_response(&state, &caller).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/watchdog",
+    request_body = WatchdogMsg,
+    responses(
+        (status = 200, body = Watchdog),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn watchdog(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<WatchdogMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Watchdog) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    info!("Watchdog {:?} requested by {}", payload.action, caller);
+    if let Err(error) = state
+        .ipmi(&caller, move |config| {
+            bmc::watchdog_action(payload.action, config)
+        })
+        .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response();
+    }
+    watchdog_response(&state, &caller).await
+}
+
+async fn watchdog_response(state: &AppState, caller: &str) -> Response {
+    match state.ipmi(caller, bmc::watchdog).await {
+        Ok(Some(watchdog)) => (StatusCode::OK, Json(watchdog)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_IMPLEMENTED,
+            "watchdog state not reported by this BMC",
+        )
+            .into_response(),
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
     }
-    (StatusCode::OK, "ok")
 }
+
+#[utoipa::path(
+    post,
+    path = "/raw",
+    request_body = RawMsg,
+    responses(
+        (status = 200, body = RawResponse),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn raw(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<RawMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Raw) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    let caller = caller.label();
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    // a raw command can do anything, including what the kill switch stops
+    if state.kill_switch_engaged() {
+        warn!("Refusing raw command, kill switch is engaged");
+        return (StatusCode::SERVICE_UNAVAILABLE, "kill switch engaged").into_response();
+    }
+    warn!(
+        "Raw command netfn {:#04x} cmd {:#04x} data {:02x?} sent by {}",
+        payload.netfn, payload.cmd, payload.data, caller
+    );
+    let result = state
+        .ipmi(&caller, move |config| {
+            ipmi::raw(payload.netfn, payload.cmd, &payload.data, config)
+        })
+        .await;
+    match result {
+        Ok(data) => {
+            warn!("Raw command by {} answered {:02x?}", caller, data);
+            (StatusCode::OK, Json(RawResponse { data })).into_response()
+        }
+        Err(error) => {
+            warn!("Raw command by {} failed: {}", caller, error.message);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse { error }),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/oem/{command}",
+    params(("command" = String, Path, description = "OEM command name")),
+    responses(
+        (status = 200, body = OemOutput),
+        (status = 404, description = "no such command for the configured vendor"),
+        (status = 500, description = "ipmitool failed", body = ErrorResponse),
+    )
+)]
+async fn oem_command(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let Some(vendor) = state.config.vendor else {
+        return (StatusCode::NOT_IMPLEMENTED, "no vendor configured").into_response();
+    };
+    let Some(command) = vendor.command(&name) else {
+        return (StatusCode::NOT_FOUND, "unknown OEM command").into_response();
+    };
+    let action = if command.admin {
+        Action::OemAdmin
+    } else {
+        Action::Oem
+    };
+    let caller = match state.authorize(&token, action) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    info!("Running OEM command {} for {}", name, caller);
+    match state
+        .ipmi(&caller, move |config| oem::run_command(command, config))
+        .await
+    {
+        Ok((output, truncated)) => {
+            (StatusCode::OK, Json(OemOutput { output, truncated })).into_response()
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error }),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/diag",
+    responses(
+        (status = 200, body = DiagReport),
+    )
+)]
+async fn get_diag(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Read) {
+        Ok(caller) => caller.label(),
+        Err(denied) => return denied.into_response(),
+    };
+    info!("Got request for BMC diagnostics from {}", caller);
+    if let Some(gone) = state.out_of_service() {
+        return gone;
+    }
+    let chassis_status = state.ipmi(&caller, |config| ipmi::run(config, &["chassis", "status"]));
+    let report = diag::diagnose(&state.config, chassis_status).await;
+    (StatusCode::OK, Json(report)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    security(()),
+    responses(
+        (status = 200, description = "ready", body = String),
+        (status = 503, description = "the BMC does not answer", body = String),
+    )
+)]
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    match rmcp::ping(&state.config.ipmi_address).await {
+        Ok(()) => match state.state_error() {
+            Some(e) => (
+                StatusCode::OK,
+                format!("ready, degraded: state file not written: {}", e),
+            ),
+            None => (StatusCode::OK, "ready".to_string()),
+        },
+        Err(e) => {
+            warn!("Readiness check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+        }
+    }
+}
+
+async fn openapi_json() -> impl IntoResponse {
+    Json(openapi::ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/enrollment-codes",
+    request_body = EnrollmentCodeMsg,
+    responses(
+        (status = 200, body = EnrollmentCodeResponse),
+    )
+)]
+async fn mint_enrollment_code(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<EnrollmentCodeMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Admin) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    if state.config.state_file.is_none() {
+        return (
+            StatusCode::CONFLICT,
+            "enrollment needs a state_file to keep tokens in",
+        )
+            .into_response();
+    }
+    if payload.ttl_secs > MAX_ENROLLMENT_SECS {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("ttl_secs is at most {}", MAX_ENROLLMENT_SECS),
+        )
+            .into_response();
+    }
+    let code = match state::random_secret() {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Failed to generate enrollment code: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to generate code").into_response();
+        }
+    };
+    let expires_at = Timestamp(SystemTime::now() + Duration::from_secs(payload.ttl_secs));
+    let mut persisted = state.persisted.lock().unwrap();
+    let mut updated = persisted.clone();
+    updated.enrollment_codes.retain(|c| !c.expires_at.is_past());
+    updated.enrollment_codes.push(EnrollmentCode {
+        code: code.clone(),
+        expires_at,
+        admin: payload.admin,
+        created_by: caller.label(),
+    });
+    if let Err(e) = state.save_state(&updated) {
+        error!("Failed to save state file: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "failed to persist state").into_response();
+    }
+    *persisted = updated;
+    info!(
+        "Enrollment code{} minted by {}, valid until {}",
+        if payload.admin {
+            " for an admin token"
+        } else {
+            ""
+        },
+        caller.label(),
+        expires_at
+    );
+    (
+        StatusCode::OK,
+        Json(EnrollmentCodeResponse { code, expires_at }),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/enroll",
+    security(()),
+    request_body = EnrollMsg,
+    responses(
+        (status = 200, body = EnrollResponse),
+        (status = 401, description = "invalid or used code"),
+    )
+)]
+async fn enroll(
+    State(state): State<AppState>,
+    Json(payload): Json<EnrollMsg>,
+) -> impl IntoResponse {
+    let token = match state::random_secret() {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to generate token: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to generate token",
+            )
+                .into_response();
+        }
+    };
+    let mut persisted = state.persisted.lock().unwrap();
+    let Some(index) = persisted
+        .enrollment_codes
+        .iter()
+        .position(|c| c.code == payload.code && !c.expires_at.is_past())
+    else {
+        warn!("Rejecting unknown or expired enrollment code");
+        return (StatusCode::UNAUTHORIZED, "invalid enrollment code").into_response();
+    };
+    let mut updated = persisted.clone();
+    let code = updated.enrollment_codes.remove(index);
+    updated.tokens.push(TokenConfig {
+        token: token.clone(),
+        description: Some(payload.owner.clone()),
+        created_at: Some(Timestamp::now()),
+        expires_at: None,
+        admin: code.admin,
+        raw: false,
+        delegated_by: None,
+        scope: None,
+    });
+    if let Err(e) = state.save_state(&updated) {
+        error!("Failed to save state file: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "failed to persist state").into_response();
+    }
+    *persisted = updated;
+    warn!(
+        "Token enrolled for {} with a code minted by {}",
+        payload.owner, code.created_by
+    );
+    (StatusCode::OK, Json(EnrollResponse { token })).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/delegate",
+    request_body = DelegateMsg,
+    responses(
+        (status = 200, body = DelegateResponse),
+    )
+)]
+async fn delegate(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<DelegateMsg>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Delegate) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    if caller.delegated_by.is_some() {
+        warn!("Refusing delegation by delegated token {}", caller.label());
+        return (StatusCode::FORBIDDEN, "delegated tokens cannot delegate").into_response();
+    }
+    if payload.scope.is_empty() {
+        return (StatusCode::BAD_REQUEST, "scope is empty").into_response();
+    }
+    // delegated tokens are never admin tokens and cannot delegate further
+    if let Some(action) = payload
+        .scope
+        .iter()
+        .find(|a| a.needs_admin() || **a == Action::Delegate)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("{:?} cannot be delegated", action),
+        )
+            .into_response();
+    }
+    if let Some(action) = payload.scope.iter().find(|a| !caller.allows(**a)) {
+        warn!(
+            "Refusing delegation of {:?} by {}, not allowed itself",
+            action,
+            caller.label()
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            format!("{:?} is not allowed for this token", action),
+        )
+            .into_response();
+    }
+    if payload.ttl_secs > MAX_DELEGATION_SECS {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("ttl_secs is at most {}", MAX_DELEGATION_SECS),
+        )
+            .into_response();
+    }
+    if state.config.state_file.is_none() {
+        return (
+            StatusCode::CONFLICT,
+            "delegation needs a state_file to keep tokens in",
+        )
+            .into_response();
+    }
+    let token = match state::random_secret() {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to generate token: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to generate token",
+            )
+                .into_response();
+        }
+    };
+    let expires_at = Timestamp(SystemTime::now() + Duration::from_secs(payload.ttl_secs));
+    let mut persisted = state.persisted.lock().unwrap();
+    let mut updated = persisted.clone();
+    // delegated tokens are short-lived, drop them once expired
+    updated
+        .tokens
+        .retain(|t| t.delegated_by.is_none() || !t.is_expired());
+    updated.tokens.push(TokenConfig {
+        token: token.clone(),
+        description: Some(payload.owner.clone()),
+        created_at: Some(Timestamp::now()),
+        expires_at: Some(expires_at),
+        admin: false,
+        raw: false,
+        delegated_by: Some(caller.label()),
+        scope: Some(payload.scope.clone()),
+    });
+    if let Err(e) = state.save_state(&updated) {
+        error!("Failed to save state file: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "failed to persist state").into_response();
+    }
+    *persisted = updated;
+    warn!(
+        "Token delegated to {} by {} for {:?}, valid until {}",
+        payload.owner,
+        caller.label(),
+        payload.scope,
+        expires_at
+    );
+    let response = DelegateResponse {
+        token,
+        expires_at,
+        scope: payload.scope,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/tokens/expiring",
+    params(ExpiringQuery),
+    responses(
+        (status = 200, body = [TokenExpiry]),
+    )
+)]
+async fn expiring_tokens(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<ExpiringQuery>,
+) -> impl IntoResponse {
+    info!("Got request for tokens expiring within {} days", query.days);
+    if !state.is_admin(&token) {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    let window = std::time::Duration::from_secs(query.days.saturating_mul(24 * 3600));
+    let expiring: Vec<TokenExpiry> = state
+        .tokens()
+        .iter()
+        .filter(|t| t.expires_within(window))
+        .map(|t| TokenExpiry {
+            description: t.description.clone(),
+            expires_at: t.expires_at,
+            expired: t.is_expired(),
+        })
+        .collect();
+    (StatusCode::OK, Json(expiring)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/access-report",
+    params(AccessReportQuery),
+    responses(
+        (status = 200, body = [AccessEntry]),
+    )
+)]
+async fn access_report(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<AccessReportQuery>,
+) -> impl IntoResponse {
+    if !state.is_admin(&token) {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    let config_tokens = state.config.tokens.len();
+    let last_used = state.last_used.lock().unwrap().clone();
+    let entries: Vec<AccessEntry> = state
+        .tokens()
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let source = if i < config_tokens {
+                "config"
+            } else if t.delegated_by.is_some() {
+                "delegated"
+            } else {
+                "enrolled"
+            };
+            AccessEntry::new(t, source, last_used.get(&t.label()).copied())
+        })
+        .collect();
+    match query.format.as_deref() {
+        None | Some("json") => (StatusCode::OK, Json(entries)).into_response(),
+        Some("csv") => {
+            let mut csv = format!("{}\n", AccessEntry::CSV_HEADER);
+            for entry in &entries {
+                csv.push_str(&entry.csv_row());
+                csv.push('\n');
+            }
+            (StatusCode::OK, [(header::CONTENT_TYPE, "text/csv")], csv).into_response()
+        }
+        Some(format) => (
+            StatusCode::BAD_REQUEST,
+            format!("unknown format: {}", format),
+        )
+            .into_response(),
+    }
+}
+
+/// Evaluates the checks a request for `action` would go through, without
+/// running anything.
+#[utoipa::path(
+    get,
+    path = "/auth/can-i",
+    params(CanIQuery),
+    responses(
+        (status = 200, body = CanI),
+    )
+)]
+async fn can_i(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<CanIQuery>,
+) -> impl IntoResponse {
+    let Some(caller) = state.find_token(&token) else {
+        return (StatusCode::UNAUTHORIZED, "token not in config").into_response();
+    };
+    let action = query.action;
+    let denied = |reason: String| CanI {
+        allowed: false,
+        reason: Some(reason),
+        reason_required: false,
+    };
+    // the admin routes and delegation keep working while the BMC is disabled
+    let disabled = state.disabled();
+    let answer = if action == Action::Raw && !caller.has_role_for(action) {
+        denied("raw token required".to_string())
+    } else if !caller.has_role_for(action) {
+        denied("admin token required".to_string())
+    } else if !caller.has_in_scope(action) {
+        denied("not in the token's scope".to_string())
+    } else if action == Action::Delegate && caller.delegated_by.is_some() {
+        denied("delegated tokens cannot delegate".to_string())
+    } else if disabled.disabled && !matches!(action, Action::Admin | Action::Delegate) {
+        let reason = disabled.reason.as_deref().unwrap_or("no reason given");
+        denied(format!("disabled: {}", reason))
+    } else if action.is_destructive() && state.kill_switch_engaged() {
+        denied("kill switch engaged".to_string())
+    } else {
+        CanI {
+            allowed: true,
+            reason: None,
+            reason_required: action.needs_reason() && state.config.require_reason,
+        }
+    };
+    (StatusCode::OK, Json(answer)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics/me",
+    responses(
+        (status = 200, body = CommandCount),
+    )
+)]
+async fn my_metrics(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let Some(caller) = state.find_token(&token).map(|t| t.label()) else {
+        return (StatusCode::UNAUTHORIZED, "token not in config").into_response();
+    };
+    let stats = state.stats.lock().unwrap();
+    let usage = stats.by_caller.get(&caller).cloned().unwrap_or_default();
+    (StatusCode::OK, Json(usage)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    responses(
+        (status = 200, body = CommandStats),
+    )
+)]
+async fn command_stats(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    info!("Got request for command stats");
+    if !state.is_admin(&token) {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    let stats = state.stats.lock().unwrap();
+    (StatusCode::OK, Json(&*stats)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/support-bundle",
+    responses(
+        (status = 200, body = SupportBundle),
+    )
+)]
+async fn support_bundle(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Admin) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    info!("Support bundle requested by {}", caller.label());
+    let bundle = SupportBundle {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        config: state.config.redacted(),
+        state: state.persisted.lock().unwrap().redacted(),
+        stats: state.stats.lock().unwrap().clone(),
+    };
+    (StatusCode::OK, Json(bundle)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/kill-switch",
+    responses(
+        (status = 200, body = KillSwitch),
+    )
+)]
+async fn get_kill_switch(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    if !state.is_admin(&token) {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    let enabled = state.kill_switch_engaged();
+    (StatusCode::OK, Json(KillSwitch { enabled })).into_response()
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/kill-switch",
+    request_body = KillSwitch,
+    responses(
+        (status = 200, body = KillSwitch),
+    )
+)]
+async fn set_kill_switch(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<KillSwitch>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Admin) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    if state.config.kill_switch && !payload.enabled {
+        return (StatusCode::CONFLICT, "kill switch is engaged in the config").into_response();
+    }
+    let mut persisted = state.persisted.lock().unwrap();
+    let mut updated = persisted.clone();
+    updated.kill_switch = payload.enabled;
+    // keep the change in memory anyway, refusing it would be worse
+    if let Err(e) = state.save_state(&updated) {
+        error!(
+            "Failed to save state file, keeping the change in memory: {}",
+            e
+        );
+    }
+    *persisted = updated;
+    warn!(
+        "Kill switch {} by {}",
+        if payload.enabled {
+            "engaged"
+        } else {
+            "released"
+        },
+        caller.label()
+    );
+    (StatusCode::OK, Json(payload)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/disabled",
+    responses(
+        (status = 200, body = Disabled),
+    )
+)]
+async fn get_disabled(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> impl IntoResponse {
+    if !state.is_admin(&token) {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    (StatusCode::OK, Json(state.disabled())).into_response()
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/disabled",
+    request_body = Disabled,
+    responses(
+        (status = 200, body = Disabled),
+    )
+)]
+async fn set_disabled(
+    State(state): State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(payload): Json<Disabled>,
+) -> impl IntoResponse {
+    let caller = match state.authorize(&token, Action::Admin) {
+        Ok(caller) => caller,
+        Err(denied) => return denied.into_response(),
+    };
+    if state.config.disabled && !payload.disabled {
+        return (StatusCode::CONFLICT, "BMC is disabled in the config").into_response();
+    }
+    let mut persisted = state.persisted.lock().unwrap();
+    let mut updated = persisted.clone();
+    updated.disabled = payload.disabled;
+    updated.disabled_reason = payload.reason.clone().filter(|_| payload.disabled);
+    // keep the change in memory anyway, refusing it would be worse
+    if let Err(e) = state.save_state(&updated) {
+        error!(
+            "Failed to save state file, keeping the change in memory: {}",
+            e
+        );
+    }
+    *persisted = updated;
+    warn!(
+        "BMC {} by {}",
+        if payload.disabled {
+            "disabled"
+        } else {
+            "enabled"
+        },
+        caller.label()
+    );
+    (StatusCode::OK, Json(payload)).into_response()
+}
+
 async fn default_404() -> impl IntoResponse {
     info!("Got request for unknown path");
     StatusCode::NOT_FOUND
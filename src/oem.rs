@@ -0,0 +1,93 @@
+//! Vendor-specific (OEM) commands, limited to a curated list per vendor so
+//! tokens cannot send arbitrary OEM requests.
+//!
+//! The Lenovo and HPE lists are empty: ipmitool has no OEM subcommands for
+//! them, and their BMCs (XClarity Controller, iLO) expose vendor features
+//! through Redfish rather than through documented OEM IPMI commands.
+
+use crate::config::Config;
+use crate::ipmi::{run, sanitize_output, PowerError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Vendor {
+    Dell,
+    Supermicro,
+    Lenovo,
+    Hpe,
+}
+
+pub struct OemCommand {
+    pub name: &'static str,
+    pub args: &'static [&'static str],
+    /// Changes BMC settings rather than only reading them, requires an admin
+    /// token.
+    pub admin: bool,
+}
+
+const DELL: &[OemCommand] = &[
+    OemCommand {
+        name: "power-monitor",
+        args: &["delloem", "powermonitor"],
+        admin: false,
+    },
+    OemCommand {
+        name: "mac-list",
+        args: &["delloem", "mac", "list"],
+        admin: false,
+    },
+    OemCommand {
+        name: "lan-mode",
+        args: &["delloem", "lan", "get"],
+        admin: false,
+    },
+];
+
+// Supermicro fan modes through OEM netfn 0x30, command 0x45
+const SUPERMICRO: &[OemCommand] = &[
+    OemCommand {
+        name: "fan-mode",
+        args: &["raw", "0x30", "0x45", "0x00"],
+        admin: false,
+    },
+    OemCommand {
+        name: "fan-mode-standard",
+        args: &["raw", "0x30", "0x45", "0x01", "0x00"],
+        admin: true,
+    },
+    OemCommand {
+        name: "fan-mode-full",
+        args: &["raw", "0x30", "0x45", "0x01", "0x01"],
+        admin: true,
+    },
+    OemCommand {
+        name: "fan-mode-optimal",
+        args: &["raw", "0x30", "0x45", "0x01", "0x02"],
+        admin: true,
+    },
+    OemCommand {
+        name: "fan-mode-heavy-io",
+        args: &["raw", "0x30", "0x45", "0x01", "0x04"],
+        admin: true,
+    },
+];
+
+impl Vendor {
+    pub fn commands(self) -> &'static [OemCommand] {
+        match self {
+            Vendor::Dell => DELL,
+            Vendor::Supermicro => SUPERMICRO,
+            Vendor::Lenovo | Vendor::Hpe => &[],
+        }
+    }
+    pub fn command(self, name: &str) -> Option<&'static OemCommand> {
+        self.commands().iter().find(|c| c.name == name)
+    }
+}
+
+/// Runs `command` and returns ipmitool's output, see `sanitize_output`, and
+/// whether it was cut.
+pub fn run_command(command: &OemCommand, config: &Config) -> Result<(String, bool), PowerError> {
+    run(config, command.args).map(|output| sanitize_output(&output, config))
+}
@@ -0,0 +1,204 @@
+//! The OpenAPI document served at `GET /openapi.json`, so typed clients can
+//! be generated from the bodies in `api` instead of following the README.
+
+use crate::api::{
+    AccessEntry, BmcPasswordMsg, BmcResetMsg, BmcUserEnabled, BmcUsers, BootDev, BootDevMsg, CanI,
+    CommandCount, CommandStats, ControlResult, DelegateMsg, DelegateResponse, Disabled, EnrollMsg,
+    EnrollResponse, EnrollmentCodeMsg, EnrollmentCodeResponse, ErrorResponse, Fan, Fans, Fru,
+    Identify, IdentifyMsg, KillSwitch, OemOutput, PefFilterEnabled, PowerControlFailure,
+    PowerControlMsg, PowerControlResponse, PowerState, PowerStatusResponse, RawMsg, RawResponse,
+    RestorePolicyMsg, Sel, Sensors, SupportBundle, Temperature, Thermal, TokenExpiry, WatchdogMsg,
+};
+use crate::bmc::{BmcInfo, BmcLan, BmcUser, ResetMode, Watchdog, WatchdogAction};
+use crate::chassis::{ChassisStatus, PowerOnHours, RestorePolicy};
+use crate::config::Action;
+use crate::dcmi::{PowerCap, PowerCapChange, PowerReading};
+use crate::diag::{DiagReport, DiagStep, Outcome};
+use crate::fru::FruDevice;
+use crate::ipmi::{BootDevice, BootOptions, IdentifyState, PowerError, PowerErrorKind};
+use crate::pef::{Pef, PefFilter};
+use crate::sel::{Direction, SelEntry, SelInfo};
+use crate::sensor::{Sensor, Thresholds};
+use crate::timestamp::Timestamp;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::get_power_status,
+        crate::power_control,
+        crate::get_power_usage,
+        crate::get_power_cap,
+        crate::set_power_cap,
+        crate::get_chassis_status,
+        crate::get_power_on_hours,
+        crate::set_restore_policy,
+        crate::get_identify,
+        crate::identify,
+        crate::set_bootdev,
+        crate::get_sensors,
+        crate::get_sensor,
+        crate::get_sensor_thresholds,
+        crate::set_sensor_thresholds,
+        crate::get_thermal,
+        crate::get_fans,
+        crate::get_sel,
+        crate::clear_sel,
+        crate::get_sel_info,
+        crate::get_fru,
+        crate::console,
+        crate::get_bmc_info,
+        crate::reset_bmc,
+        crate::get_bmc_lan,
+        crate::bmc_users,
+        crate::set_bmc_password,
+        crate::set_bmc_user_enabled,
+        crate::get_pef,
+        crate::set_pef_filter_enabled,
+        crate::get_watchdog,
+        crate::watchdog,
+        crate::get_diag,
+        crate::raw,
+        crate::oem_command,
+        crate::readyz,
+        crate::my_metrics,
+        crate::can_i,
+        crate::enroll,
+        crate::delegate,
+        crate::mint_enrollment_code,
+        crate::expiring_tokens,
+        crate::access_report,
+        crate::command_stats,
+        crate::support_bundle,
+        crate::get_kill_switch,
+        crate::set_kill_switch,
+        crate::get_disabled,
+        crate::set_disabled,
+    ),
+    components(schemas(
+        AccessEntry,
+        Action,
+        BmcInfo,
+        BmcLan,
+        BmcPasswordMsg,
+        BmcResetMsg,
+        BmcUser,
+        BmcUserEnabled,
+        BmcUsers,
+        BootDev,
+        BootDevMsg,
+        BootDevice,
+        BootOptions,
+        CanI,
+        ChassisStatus,
+        CommandCount,
+        CommandStats,
+        ControlResult,
+        DelegateMsg,
+        DelegateResponse,
+        DiagReport,
+        DiagStep,
+        Direction,
+        Disabled,
+        EnrollMsg,
+        EnrollResponse,
+        EnrollmentCodeMsg,
+        EnrollmentCodeResponse,
+        ErrorResponse,
+        Fan,
+        Fans,
+        Fru,
+        FruDevice,
+        Identify,
+        IdentifyMsg,
+        IdentifyState,
+        KillSwitch,
+        OemOutput,
+        Outcome,
+        Pef,
+        PefFilter,
+        PefFilterEnabled,
+        PowerCap,
+        PowerCapChange,
+        PowerControlFailure,
+        PowerControlMsg,
+        PowerControlResponse,
+        PowerError,
+        PowerErrorKind,
+        PowerOnHours,
+        PowerReading,
+        PowerState,
+        PowerStatusResponse,
+        RawMsg,
+        RawResponse,
+        ResetMode,
+        RestorePolicy,
+        RestorePolicyMsg,
+        Sel,
+        SelEntry,
+        SelInfo,
+        Sensor,
+        Sensors,
+        SupportBundle,
+        Temperature,
+        Thermal,
+        Thresholds,
+        Timestamp,
+        TokenExpiry,
+        Watchdog,
+        WatchdogAction,
+        WatchdogMsg,
+    )),
+    modifiers(&BearerToken),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// Declares the `Authorization: Bearer` token the routes take.
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `$ref` has to point at a listed schema, or generated clients
+    /// miss the type.
+    #[test]
+    fn every_referenced_schema_is_listed() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        let mut refs = Vec::new();
+        collect_refs(&doc, &mut refs);
+        assert!(!refs.is_empty());
+        for reference in refs {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("unexpected reference {}", reference));
+            assert!(schemas.contains_key(name), "{} is not listed", name);
+        }
+    }
+
+    fn collect_refs(value: &serde_json::Value, refs: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if let Some(serde_json::Value::String(reference)) = map.get("$ref") {
+                    refs.push(reference.clone());
+                }
+                map.values().for_each(|v| collect_refs(v, refs));
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|v| collect_refs(v, refs)),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Platform Event Filtering: which events the BMC acts on, from `ipmitool
+//! pef`.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct Pef {
+    /// Every `pef status` line as printed, e.g. `PEF status`.
+    pub status: BTreeMap<String, String>,
+    pub filters: Vec<PefFilter>,
+}
+
+/// An entry of the event filter table.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PefFilter {
+    pub id: u8,
+    pub enabled: bool,
+    /// Every field as printed, e.g. `Sensor type`, `Event severity` and
+    /// `Action`.
+    pub fields: BTreeMap<String, String>,
+}
+
+pub fn pef(config: &Config) -> Result<Pef, PowerError> {
+    let status = parse_fields(&run(config, &["pef", "status"])?);
+    let filters = parse_filters(&run(config, &["pef", "filter", "list"])?);
+    Ok(Pef { status, filters })
+}
+
+pub fn set_filter_enabled(id: u8, enabled: bool, config: &Config) -> Result<(), PowerError> {
+    let id = id.to_string();
+    let action = if enabled { "enable" } else { "disable" };
+    run(config, &["pef", "filter", action, &id]).map(|_| ())
+}
+
+fn parse_fields(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Each entry starts with its `PEF table entry : <id>` line.
+fn parse_filters(output: &str) -> Vec<PefFilter> {
+    let mut filters: Vec<PefFilter> = Vec::new();
+    for (key, value) in output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+    {
+        if key == "PEF table entry" {
+            if let Ok(id) = value.parse() {
+                filters.push(PefFilter {
+                    id,
+                    enabled: false,
+                    fields: BTreeMap::new(),
+                });
+            }
+            continue;
+        }
+        let Some(filter) = filters.last_mut() else {
+            continue;
+        };
+        if key == "Status" {
+            filter.enabled = value.starts_with("active") || value.starts_with("enabled");
+        }
+        filter.fields.insert(key.to_string(), value.to_string());
+    }
+    filters
+}
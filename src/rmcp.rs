@@ -0,0 +1,67 @@
+//! RMCP/ASF presence ping: a sessionless check that a BMC answers on UDP 623,
+//! much cheaper than a full ipmitool session.
+
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const RMCP_PORT: u16 = 623;
+const ATTEMPTS: u8 = 3;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const ASF_IANA: [u8; 4] = [0x00, 0x00, 0x11, 0xbe];
+const PRESENCE_PING: u8 = 0x80;
+const PRESENCE_PONG: u8 = 0x40;
+
+fn ping_packet(tag: u8) -> [u8; 12] {
+    [
+        // RMCP header: version 1.0, reserved, no ack requested, ASF class
+        0x06,
+        0x00,
+        0xff,
+        0x06,
+        // ASF message
+        ASF_IANA[0],
+        ASF_IANA[1],
+        ASF_IANA[2],
+        ASF_IANA[3],
+        PRESENCE_PING,
+        tag,
+        0x00,
+        0x00,
+    ]
+}
+
+fn is_pong(packet: &[u8], tag: u8) -> bool {
+    packet.len() >= 12
+        && packet[3] == 0x06
+        && packet[4..8] == ASF_IANA
+        && packet[8] == PRESENCE_PONG
+        && packet[9] == tag
+}
+
+/// Sends presence pings to `address` until one is answered.
+pub async fn ping(address: &str) -> anyhow::Result<()> {
+    let target = tokio::net::lookup_host((address, RMCP_PORT))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for {}", address))?;
+    let bind = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(target).await?;
+    let mut buf = [0u8; 64];
+    for tag in 0..ATTEMPTS {
+        socket.send(&ping_packet(tag)).await?;
+        let deadline = tokio::time::Instant::now() + ATTEMPT_TIMEOUT;
+        // skip stray or late replies to earlier attempts
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            if is_pong(&buf[..received?], tag) {
+                return Ok(());
+            }
+        }
+    }
+    anyhow::bail!("No RMCP presence pong from {}", address)
+}
@@ -0,0 +1,145 @@
+//! The System Event Log, from `ipmitool sel`.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use crate::timestamp::Timestamp;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct SelEntry {
+    /// Record ID as printed by ipmitool, hexadecimal.
+    pub id: String,
+    /// Missing for events logged before the BMC clock was set (`Pre-Init`).
+    /// BMC clocks are taken to be UTC.
+    pub timestamp: Option<Timestamp>,
+    /// E.g. `Temperature CPU Temp`.
+    pub sensor: String,
+    /// E.g. `Upper Critical going high`.
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    /// Anything ipmitool adds, e.g. `Reading 95 > Threshold 90 degrees C`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Asserted,
+    Deasserted,
+}
+
+/// From `ipmitool sel info`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct SelInfo {
+    pub entries: u64,
+    pub free_space_bytes: u64,
+    /// The log filled up and events were dropped.
+    pub overflow: bool,
+    pub last_add_time: Option<Timestamp>,
+    pub last_erase_time: Option<Timestamp>,
+}
+
+pub fn sel(config: &Config) -> Result<Vec<SelEntry>, PowerError> {
+    let output = run(config, &["sel", "elist"])?;
+    Ok(output.lines().filter_map(parse_sel_line).collect())
+}
+
+pub fn info(config: &Config) -> Result<Option<SelInfo>, PowerError> {
+    let output = run(config, &["sel", "info"])?;
+    Ok(parse_info(&output))
+}
+
+/// Clears the log. Its content is gone for good, so this is destructive.
+pub fn clear(config: &Config) -> Result<(), PowerError> {
+    run(config, &["sel", "clear"]).map(|_| ())
+}
+
+/// Parses the `key : value` lines of `ipmitool sel info`, e.g.
+/// `Free Space : 15984 bytes` and `Last Add Time : 07/15/2024 10:12:33`.
+fn parse_info(output: &str) -> Option<SelInfo> {
+    let fields: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    let number = |key: &str| field(key)?.split_whitespace().next()?.parse().ok();
+    // `Not Available` when nothing was added or erased yet
+    let time = |key: &str| {
+        let (date, time) = field(key)?.split_once(' ')?;
+        parse_timestamp(date, time.trim())
+    };
+    Some(SelInfo {
+        entries: number("Entries")?,
+        free_space_bytes: number("Free Space")?,
+        overflow: field("Overflow") == Some("true"),
+        last_add_time: time("Last Add Time"),
+        last_erase_time: time("Last Del Time"),
+    })
+}
+
+/// Parses a line like
+/// `1f | 07/15/2024 | 10:12:33 | Temperature CPU Temp | Upper Critical going high | Asserted`.
+fn parse_sel_line(line: &str) -> Option<SelEntry> {
+    let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+    let [id, date, time, sensor, event, rest @ ..] = columns.as_slice() else {
+        return None;
+    };
+    let (direction, detail) = match rest.first() {
+        Some(&"Asserted") => (Some(Direction::Asserted), &rest[1..]),
+        Some(&"Deasserted") => (Some(Direction::Deasserted), &rest[1..]),
+        _ => (None, rest),
+    };
+    Some(SelEntry {
+        id: id.to_string(),
+        timestamp: parse_timestamp(date, time),
+        sensor: sensor.to_string(),
+        event: event.to_string(),
+        direction,
+        detail: (!detail.is_empty()).then(|| detail.join(" | ")),
+    })
+}
+
+/// ipmitool prints dates as `MM/DD/YYYY`.
+fn parse_timestamp(date: &str, time: &str) -> Option<Timestamp> {
+    let mut parts = date.split('/');
+    let (month, day, year) = (parts.next()?, parts.next()?, parts.next()?);
+    format!("{}-{}-{}T{}Z", year, month, day, time).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sel_line_reads_captured_output() {
+        let output = "\
+   1 | Pre-Init  | 0000000000 | System Event #0x83 | Timestamp Clock Sync | Asserted
+  1f | 07/15/2024 | 10:12:33 | Temperature CPU Temp | Upper Critical going high | Asserted | Reading 95 > Threshold 90 degrees C
+  20 | 07/15/2024 | 10:14:02 | Temperature CPU Temp | Upper Critical going high | Deasserted
+  21 | 07/16/2024 | 08:00:00 | Power Supply PS1 Status | Failure detected
+";
+        let entries: Vec<SelEntry> = output.lines().filter_map(parse_sel_line).collect();
+        assert_eq!(entries.len(), 4);
+        assert!(entries[0].timestamp.is_none());
+        let alarm = &entries[1];
+        assert_eq!(alarm.id, "1f");
+        assert_eq!(
+            alarm.timestamp.map(|t| t.to_string()).as_deref(),
+            Some("2024-07-15T10:12:33Z")
+        );
+        assert_eq!(alarm.sensor, "Temperature CPU Temp");
+        assert_eq!(alarm.event, "Upper Critical going high");
+        assert!(matches!(alarm.direction, Some(Direction::Asserted)));
+        assert_eq!(
+            alarm.detail.as_deref(),
+            Some("Reading 95 > Threshold 90 degrees C")
+        );
+        assert!(matches!(entries[2].direction, Some(Direction::Deasserted)));
+        assert!(entries[3].direction.is_none());
+        assert!(entries[3].detail.is_none());
+    }
+}
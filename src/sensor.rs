@@ -0,0 +1,263 @@
+//! Sensor readings from `ipmitool sensor`, which unlike `sdr elist` includes
+//! the thresholds.
+
+use crate::config::Config;
+use crate::ipmi::{run, PowerError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct Sensor {
+    pub name: String,
+    /// Missing for discrete sensors and sensors without a reading.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    /// E.g. `degrees C`, `RPM` or `discrete`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// `ok`, `nc` (non-critical), `cr` (critical), `nr` (non-recoverable),
+    /// `na` without a reading, or a hex state for discrete sensors.
+    pub state: String,
+    pub thresholds: Thresholds,
+}
+
+/// Thresholds the BMC has set for a sensor, from lowest to highest.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Default)]
+pub struct Thresholds {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_non_recoverable: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_non_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upper_non_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upper_critical: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upper_non_recoverable: Option<f64>,
+}
+
+impl Thresholds {
+    fn levels(&self) -> [(&'static str, Option<f64>); 6] {
+        [
+            ("lnr", self.lower_non_recoverable),
+            ("lcr", self.lower_critical),
+            ("lnc", self.lower_non_critical),
+            ("unc", self.upper_non_critical),
+            ("ucr", self.upper_critical),
+            ("unr", self.upper_non_recoverable),
+        ]
+    }
+    /// `self` with the thresholds given in `changes` replaced.
+    pub fn merged(&self, changes: &Thresholds) -> Thresholds {
+        Thresholds {
+            lower_non_recoverable: changes.lower_non_recoverable.or(self.lower_non_recoverable),
+            lower_critical: changes.lower_critical.or(self.lower_critical),
+            lower_non_critical: changes.lower_non_critical.or(self.lower_non_critical),
+            upper_non_critical: changes.upper_non_critical.or(self.upper_non_critical),
+            upper_critical: changes.upper_critical.or(self.upper_critical),
+            upper_non_recoverable: changes.upper_non_recoverable.or(self.upper_non_recoverable),
+        }
+    }
+    /// Refuses thresholds that are not finite or not in ascending order,
+    /// ignoring the ones not set.
+    pub fn check_order(&self) -> Result<(), String> {
+        let set: Vec<(&str, f64)> = self
+            .levels()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect();
+        if let Some((name, _)) = set.iter().find(|(_, value)| !value.is_finite()) {
+            return Err(format!("{} is not a number", name));
+        }
+        match set.windows(2).find(|pair| pair[0].1 > pair[1].1) {
+            Some(pair) => Err(format!("{} is above {}", pair[0].0, pair[1].0)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sensor {
+    /// The reading in degrees Celsius, for temperature sensors.
+    pub fn celsius(&self) -> Option<f64> {
+        match self.unit.as_deref()? {
+            "degrees C" => self.value,
+            "degrees F" => self.value.map(|f| (f - 32.0) * 5.0 / 9.0),
+            "degrees K" => self.value.map(|k| k - 273.15),
+            _ => None,
+        }
+    }
+}
+
+pub fn sensors(config: &Config) -> Result<Vec<Sensor>, PowerError> {
+    let output = run(config, &["sensor", "list"])?;
+    Ok(output.lines().filter_map(parse_sensor_line).collect())
+}
+
+/// Reads a single sensor, `None` if the BMC has no sensor of that name.
+pub fn sensor(name: &str, config: &Config) -> Result<Option<Sensor>, PowerError> {
+    match run(config, &["sensor", "get", name]) {
+        Ok(output) => Ok(parse_sensor_get(name, &output)),
+        Err(e) if e.message.contains("not found") => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sets the thresholds given in `changes`, one ipmitool run each.
+pub fn set_thresholds(name: &str, changes: &Thresholds, config: &Config) -> Result<(), PowerError> {
+    for (level, value) in changes.levels() {
+        if let Some(value) = value {
+            let value = value.to_string();
+            run(config, &["sensor", "thresh", name, level, &value])?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses the `key : value` lines of `ipmitool sensor get`, e.g.
+/// `Sensor Reading : 45 (+/- 0) degrees C` and `Upper Critical : 95.000`.
+fn parse_sensor_get(name: &str, output: &str) -> Option<Sensor> {
+    let fields: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    field("Sensor ID")?;
+    let (value, unit) = match field("Sensor Reading").and_then(|r| r.split_once(' ')) {
+        Some((value, rest)) if number(value).is_some() => {
+            // the unit follows the tolerance, `(+/- 0)`
+            let unit = rest.rsplit_once(')').map_or(rest, |(_, unit)| unit).trim();
+            (number(value), (!unit.is_empty()).then(|| unit.to_string()))
+        }
+        _ => (None, None),
+    };
+    let threshold = |key: &str| field(key).and_then(number);
+    Some(Sensor {
+        name: name.to_string(),
+        value,
+        unit,
+        state: field("Status").unwrap_or("na").to_string(),
+        thresholds: Thresholds {
+            lower_non_recoverable: threshold("Lower Non-Recoverable"),
+            lower_critical: threshold("Lower Critical"),
+            lower_non_critical: threshold("Lower Non-Critical"),
+            upper_non_critical: threshold("Upper Non-Critical"),
+            upper_critical: threshold("Upper Critical"),
+            upper_non_recoverable: threshold("Upper Non-Recoverable"),
+        },
+    })
+}
+
+/// Parses a line like
+/// `CPU Temp | 45.000 | degrees C | ok | na | 0.000 | 5.000 | 90.000 | 95.000 | na`.
+fn parse_sensor_line(line: &str) -> Option<Sensor> {
+    let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+    let [name, value, unit, state, thresholds @ ..] = columns.as_slice() else {
+        return None;
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let threshold = |i: usize| thresholds.get(i).and_then(|t| number(t));
+    Some(Sensor {
+        name: name.to_string(),
+        value: number(value),
+        unit: (!unit.is_empty()).then(|| unit.to_string()),
+        state: state.to_string(),
+        thresholds: Thresholds {
+            lower_non_recoverable: threshold(0),
+            lower_critical: threshold(1),
+            lower_non_critical: threshold(2),
+            upper_non_critical: threshold(3),
+            upper_critical: threshold(4),
+            upper_non_recoverable: threshold(5),
+        },
+    })
+}
+
+/// ipmitool writes `na` for missing numbers and hex for discrete states.
+fn number(column: &str) -> Option<f64> {
+    if column.starts_with("0x") {
+        return None;
+    }
+    column.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds(lower: [Option<f64>; 3], upper: [Option<f64>; 3]) -> Thresholds {
+        Thresholds {
+            lower_non_recoverable: lower[0],
+            lower_critical: lower[1],
+            lower_non_critical: lower[2],
+            upper_non_critical: upper[0],
+            upper_critical: upper[1],
+            upper_non_recoverable: upper[2],
+        }
+    }
+
+    #[test]
+    fn check_order_refuses_thresholds_out_of_order() {
+        let ordered = thresholds(
+            [Some(0.0), Some(5.0), Some(10.0)],
+            [Some(80.0), Some(90.0), None],
+        );
+        assert!(ordered.check_order().is_ok());
+        let swapped = thresholds([None, Some(12.0), Some(10.0)], [None, None, None]);
+        assert_eq!(swapped.check_order().unwrap_err(), "lcr is above lnc");
+        let not_a_number = thresholds([None, None, None], [Some(f64::NAN), None, None]);
+        assert_eq!(
+            not_a_number.check_order().unwrap_err(),
+            "unc is not a number"
+        );
+    }
+
+    #[test]
+    fn merged_keeps_the_thresholds_not_given() {
+        let current = thresholds(
+            [Some(0.0), Some(5.0), Some(10.0)],
+            [Some(80.0), Some(90.0), Some(95.0)],
+        );
+        let changes = thresholds([None, None, None], [Some(85.0), None, None]);
+        let merged = current.merged(&changes);
+        assert_eq!(merged.lower_critical, Some(5.0));
+        assert_eq!(merged.upper_non_critical, Some(85.0));
+        assert_eq!(merged.upper_critical, Some(90.0));
+        // the order is checked on the merged set, not on the changes alone
+        let too_high = thresholds([None, None, None], [Some(92.0), None, None]);
+        assert!(too_high.check_order().is_ok());
+        assert_eq!(
+            current.merged(&too_high).check_order().unwrap_err(),
+            "unc is above ucr"
+        );
+    }
+
+    #[test]
+    fn parse_sensor_line_reads_captured_output() {
+        let output = "\
+CPU Temp         | 45.000     | degrees C  | ok    | 0.000     | 5.000     | 10.000    | 85.000    | 90.000    | 95.000
+FAN1             | 4200.000   | RPM        | ok    | 300.000   | 500.000   | 700.000   | 25300.000 | 25400.000 | 25500.000
+PS1 Status       | 0x1        | discrete   | 0x0100| na        | na        | na        | na        | na        | na
+Vcpu             | na         | Volts      | na    | na        | na        | na        | na        | na        | na
+";
+        let sensors: Vec<Sensor> = output.lines().filter_map(parse_sensor_line).collect();
+        assert_eq!(sensors.len(), 4);
+        let cpu = &sensors[0];
+        assert_eq!(cpu.name, "CPU Temp");
+        assert_eq!(cpu.value, Some(45.0));
+        assert_eq!(cpu.unit.as_deref(), Some("degrees C"));
+        assert_eq!(cpu.state, "ok");
+        assert_eq!(cpu.thresholds.lower_critical, Some(5.0));
+        assert_eq!(cpu.thresholds.upper_non_recoverable, Some(95.0));
+        assert_eq!(sensors[1].celsius(), None);
+        // discrete states are hex, not readings
+        assert_eq!(sensors[2].value, None);
+        assert_eq!(sensors[2].state, "0x0100");
+        assert_eq!(sensors[3].value, None);
+        assert_eq!(sensors[3].thresholds.upper_critical, None);
+    }
+}
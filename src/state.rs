@@ -0,0 +1,221 @@
+//! Runtime state that has to survive restarts, kept in the optional
+//! `state_file`.
+
+use crate::config::TokenConfig;
+use crate::timestamp::Timestamp;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::time::Duration;
+
+/// Layout version of the state file written by this build, stored in it as
+/// `version`. Files from older builds are upgraded on load, files from newer
+/// ones are refused rather than silently losing what this build does not know.
+const VERSION: u64 = 1;
+
+/// `MIGRATIONS[i]` upgrades a state file from version `i` to `i + 1`.
+const MIGRATIONS: &[fn(&mut Mapping)] = &[
+    // 0 -> 1: files from before versioning, the layout is unchanged
+    |_| {},
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PersistedState {
+    #[serde(default)]
+    pub kill_switch: bool,
+    /// Set through `/admin/disabled`, see `Config::disabled`.
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub disabled_reason: Option<String>,
+    #[serde(default)]
+    pub convergence: Convergence,
+    /// Tokens handed out through `/auth/enroll`, valid next to the config's.
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub enrollment_codes: Vec<EnrollmentCode>,
+}
+
+/// A one-time code minted by an admin, exchanged for a token by a client.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnrollmentCode {
+    pub code: String,
+    pub expires_at: Timestamp,
+    /// Whether the token it is exchanged for is an admin token.
+    pub admin: bool,
+    /// Label of the admin token that minted it.
+    pub created_by: String,
+}
+
+/// 32 random bytes from the OS, hex encoded, for codes and tokens.
+pub fn random_secret() -> anyhow::Result<String> {
+    use std::io::Read;
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Typical seconds between requesting a power change and a status reading
+/// showing it, as a moving average.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Convergence {
+    pub to_on_secs: Option<f64>,
+    pub to_off_secs: Option<f64>,
+}
+
+impl Convergence {
+    pub fn record(&mut self, to_on: bool, took: Duration) {
+        let average = if to_on {
+            &mut self.to_on_secs
+        } else {
+            &mut self.to_off_secs
+        };
+        let sample = took.as_secs_f64();
+        *average = Some(match *average {
+            Some(average) => 0.7 * average + 0.3 * sample,
+            None => sample,
+        });
+    }
+    pub fn estimate(&self, to_on: bool) -> Option<Duration> {
+        let average = if to_on {
+            self.to_on_secs
+        } else {
+            self.to_off_secs
+        };
+        average.map(Duration::from_secs_f64)
+    }
+}
+
+impl PersistedState {
+    /// A copy safe to hand out, with token values and enrollment codes
+    /// replaced.
+    pub fn redacted(&self) -> PersistedState {
+        let mut state = self.clone();
+        for token in &mut state.tokens {
+            token.token = "<redacted>".to_string();
+        }
+        for code in &mut state.enrollment_codes {
+            code.code = "<redacted>".to_string();
+        }
+        state
+    }
+    /// Reads the state file, starting from defaults if it does not exist yet.
+    /// A file from an older build is upgraded and written back.
+    pub fn load(path: Option<&str>) -> anyhow::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut state = match serde_yaml::from_str(&content)? {
+            Value::Mapping(state) => state,
+            Value::Null => Mapping::new(),
+            _ => anyhow::bail!("State file {} is not a map", path),
+        };
+        let version = match state.remove("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("Invalid state file version: {:?}", version))?,
+            None => 0,
+        };
+        if version > VERSION {
+            anyhow::bail!(
+                "State file {} has version {}, this build only knows up to {}",
+                path,
+                version,
+                VERSION
+            );
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut state);
+        }
+        let state: Self = serde_yaml::from_value(Value::Mapping(state))?;
+        if version < VERSION {
+            info!(
+                "Upgrading state file {} from version {} to {}",
+                path, version, VERSION
+            );
+            state.save(Some(path))?;
+        }
+        Ok(state)
+    }
+    pub fn save(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let Some(path) = path else {
+            warn!("No state_file configured, the change is lost on restart");
+            return Ok(());
+        };
+        let Value::Mapping(mut state) = serde_yaml::to_value(self)? else {
+            unreachable!("PersistedState serializes to a map");
+        };
+        state.insert("version".into(), VERSION.into());
+        // write and rename so a crash never leaves a truncated file behind;
+        // only the service may read it, it holds tokens and enrollment codes
+        let tmp = format!("{}.tmp", path);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        // a tmp file left behind by a crash keeps its old mode otherwise
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file.write_all(serde_yaml::to_string(&state)?.as_bytes())?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temp directory unique to this test.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("state-{}-{}.yaml", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn load_upgrades_a_version_0_file() {
+        let path = temp_path("v0");
+        std::fs::write(&path, "kill_switch: true\ndisabled_reason: rack move\n").unwrap();
+        let state = PersistedState::load(Some(&path)).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(state.kill_switch);
+        assert_eq!(state.disabled_reason.as_deref(), Some("rack move"));
+        let written: Mapping = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(written.get("version"), Some(&Value::from(VERSION)));
+        assert_eq!(written.get("kill_switch"), Some(&Value::from(true)));
+    }
+
+    #[test]
+    fn load_refuses_a_newer_file_and_leaves_it_alone() {
+        let path = temp_path("newer");
+        let content = format!(
+            "version: {}\nkill_switch: true\nfuture_field: 1\n",
+            VERSION + 1
+        );
+        std::fs::write(&path, &content).unwrap();
+        let loaded = PersistedState::load(Some(&path));
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+        assert_eq!(written, content);
+    }
+
+    #[test]
+    fn save_keeps_the_file_private() {
+        let path = temp_path("mode");
+        PersistedState::default().save(Some(&path)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
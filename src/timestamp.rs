@@ -0,0 +1,90 @@
+//! Timestamps as they appear in the config and on the wire: RFC3339 in UTC,
+//! e.g. `2026-01-31T00:00:00Z`. Where clients give a time they may also write
+//! it relative to now, e.g. `-1h` or `-2d 12h`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use utoipa::openapi::{KnownFormat, ObjectBuilder, RefOr, Schema, SchemaFormat, SchemaType};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(pub SystemTime);
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp(SystemTime::now())
+    }
+    pub fn is_past(&self) -> bool {
+        self.0 <= SystemTime::now()
+    }
+    /// A window reaching past what `SystemTime` can hold covers every
+    /// timestamp.
+    pub fn is_within(&self, window: Duration) -> bool {
+        SystemTime::now()
+            .checked_add(window)
+            .is_none_or(|end| self.0 <= end)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(ago) = s.strip_prefix('-') {
+            let ago = humantime::parse_duration(ago.trim())
+                .map_err(|e| anyhow::anyhow!("Invalid relative time {}: {}", s, e))?;
+            return SystemTime::now()
+                .checked_sub(ago)
+                .map(Timestamp)
+                .ok_or_else(|| anyhow::anyhow!("Relative time out of range: {}", s));
+        }
+        humantime::parse_rfc3339_weak(s)
+            .map(Timestamp)
+            .map_err(|e| anyhow::anyhow!("Invalid timestamp {}: {}", s, e))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        humantime::format_rfc3339_seconds(self.0).fmt(f)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl<'s> ToSchema<'s> for Timestamp {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let schema = ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+            .description(Some("RFC3339 in UTC, e.g. `2026-01-31T00:00:00Z`"));
+        ("Timestamp", schema.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_within_a_huge_window() {
+        let timestamp: Timestamp = "2999-01-01T00:00:00Z".parse().unwrap();
+        assert!(!timestamp.is_within(Duration::from_secs(3600)));
+        assert!(timestamp.is_within(Duration::from_secs(u64::MAX)));
+    }
+}